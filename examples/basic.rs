@@ -4,10 +4,11 @@ extern crate gdbstub;
 extern crate env_logger;
 
 use std::net::TcpListener;
-use gdbstub::{GdbStub, StubCalls};
+use gdbstub::{Console, GdbStub, StopReason, StubCalls};
+use gdbstub::record::Recorder;
 use gdbstub::targets::x86;
 
-const MEMORY: &'static [u8] = &[
+const MEMORY: &[u8] = &[
     0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, // 0x7
     0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, // 0xf
     0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, 0x90, // 0x17
@@ -22,57 +23,64 @@ const MEMORY: &'static [u8] = &[
 struct DummyTarget<'a> {
     regs: x86::X86Registers,
     mem: &'a mut [u8],
+    /// Whether the last single step hit a breakpoint.
+    stopped: bool,
 }
 
 impl<'a> DummyTarget<'a> {
     fn new(mem: &'a mut [u8]) -> Self {
         Self {
-            regs: x86::X86Registers {
-                eax: 0,
-                ebx: 0,
-                ecx: 0,
-                edx: 0,
-                esp: 0,
-                ebp: 0,
-                esi: 0,
-                edi: 0,
-                eip: 0x10,
-                eflags: 0,
-                cs: 0,
-                ss: 0,
-                ds: 0,
-                es: 0,
-                fs: 0,
-                gs: 0,
-
-                st0: [0; 10],
-                st1: [0; 10],
-                st2: [0; 10],
-                st3: [0; 10],
-                st4: [0; 10],
-                st5: [0; 10],
-                st6: [0; 10],
-                st7: [0; 10],
-                fctrl: 0,
-                fstat: 0,
-                ftag: 0,
-                fiseg: 0,
-                fioff: 0,
-                foseg: 0,
-                fooff: 0,
-                fop: !0,
-
-                xmm0: 0,
-                xmm1: 0,
-                xmm2: 0,
-                xmm3: 0,
-                xmm4: 0,
-                xmm5: 0,
-                xmm6: 0,
-                xmm7: 0,
-                mxcsr: !0,
-            },
+            regs: Self::initial_regs(),
             mem,
+            stopped: false,
+        }
+    }
+
+    fn initial_regs() -> x86::X86Registers {
+        x86::X86Registers {
+            eax: 0,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            eip: 0x10,
+            eflags: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            es: 0,
+            fs: 0,
+            gs: 0,
+
+            st0: [0; 10],
+            st1: [0; 10],
+            st2: [0; 10],
+            st3: [0; 10],
+            st4: [0; 10],
+            st5: [0; 10],
+            st6: [0; 10],
+            st7: [0; 10],
+            fctrl: 0,
+            fstat: 0,
+            ftag: 0,
+            fiseg: 0,
+            fioff: 0,
+            foseg: 0,
+            fooff: 0,
+            fop: !0,
+
+            xmm0: 0,
+            xmm1: 0,
+            xmm2: 0,
+            xmm3: 0,
+            xmm4: 0,
+            xmm5: 0,
+            xmm6: 0,
+            xmm7: 0,
+            mxcsr: !0,
         }
     }
 
@@ -125,12 +133,31 @@ impl<'a> StubCalls for DummyTarget<'a> {
         }
     }
 
-    fn cont(&mut self) {
+    fn cont(&mut self, _console: &mut Console) -> StopReason {
         while !self.step() {}
+        StopReason::Trap
+    }
+
+    fn step(&mut self, _console: &mut Console) -> StopReason {
+        self.stopped = self.step();
+        StopReason::Trap
+    }
+
+    fn step_stopped(&mut self) -> bool {
+        // the recorder continues by stepping, and needs to know when an
+        // `int3` was hit
+        self.stopped
+    }
+
+    fn step_writes(&mut self) -> Option<Vec<(u64, u8)>> {
+        // executing `nop`s doesn't write memory, so the recorder doesn't have
+        // to look for changes
+        Some(Vec::new())
     }
 
-    fn step(&mut self) {
-        self.step();
+    fn restart(&mut self) -> Result<(), ()> {
+        self.regs = Self::initial_regs();
+        Ok(())
     }
 }
 
@@ -141,11 +168,13 @@ fn main() {
     let (stream, addr) = TcpListener::bind("127.0.0.1:9001").unwrap().accept().unwrap();
     println!("Incoming Connection from {}", addr);
 
+    // Record the execution, which allows running the target backwards with
+    // `reverse-stepi` and `reverse-continue`.
     let mut mem = Vec::from(MEMORY);
-    let stub = GdbStub::new(stream, DummyTarget::new(&mut mem));
+    let stub = GdbStub::new(stream, Recorder::new(DummyTarget::new(&mut mem)));
 
     match stub.poll() {
-        Ok(()) => {}
+        Ok(reason) => println!("Debugger disconnected: {:?}", reason),
         Err(e) => eprintln!("Lost debugger connection: {:?}", e),
     }
 }
@@ -0,0 +1,709 @@
+//! An interpreter for GDB's agent expressions.
+//!
+//! Agent expressions are bytecode programs GDB compiles from source-level
+//! expressions, so that the stub can evaluate them without a round-trip to the
+//! debugger (eg. for breakpoint conditions and tracepoint collection). The
+//! bytecode operates on a stack of 64-bit values and can access the target's
+//! registers and memory, as well as trace state variables.
+//!
+//! See the "Agent Expressions" appendix of the GDB manual for the format.
+
+use StubCalls;
+use trace::{TraceVariable, TRACE_BUFFER_SIZE};
+use targets::{raw_registers, RegDesc, TargetDesc};
+use utils::read_uint;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::{fmt, iter, mem};
+
+/// Maximum number of values on the stack.
+const STACK_LIMIT: usize = 100;
+
+/// Maximum length of a string printed with `%s`.
+const STRING_LIMIT: usize = 4096;
+
+/// Maximum number of instructions run by one evaluation, so that a loop in
+/// the bytecode can't hang the stub.
+const INSTRUCTION_LIMIT: usize = 100_000;
+
+/// An error that aborted the evaluation of an agent expression.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AgentError {
+    /// The bytecode contains an unknown or unsupported opcode.
+    InvalidOpcode(u8),
+    /// The bytecode ended without an `end` instruction, or jumped outside of
+    /// the bytecode.
+    Truncated,
+    /// An instruction needed more values than there were on the stack.
+    StackUnderflow,
+    /// Too many values were pushed onto the stack.
+    StackOverflow,
+    /// A division or remainder by zero.
+    DivisionByZero,
+    /// Target memory at the address couldn't be read, or the accessed range
+    /// starting there wraps around the address space.
+    Memory(u64),
+    /// The register doesn't exist or is wider than 64 bits.
+    Register(u16),
+    /// The trace state variable doesn't exist.
+    Variable(u32),
+    /// The format string of a `printf` is malformed or uses an unsupported
+    /// conversion.
+    InvalidFormat,
+    /// The evaluation ran more than `INSTRUCTION_LIMIT` instructions.
+    TooManyInstructions,
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AgentError::InvalidOpcode(op) => write!(f, "invalid opcode {:#04x}", op),
+            AgentError::Truncated => f.write_str("bytecode ended unexpectedly"),
+            AgentError::StackUnderflow => f.write_str("stack underflow"),
+            AgentError::StackOverflow => f.write_str("stack overflow"),
+            AgentError::DivisionByZero => f.write_str("division by zero"),
+            AgentError::Memory(addr) => write!(f, "can't read memory at {:#x}", addr),
+            AgentError::Register(reg) => write!(f, "can't read register {}", reg),
+            AgentError::Variable(var) => write!(f, "unknown trace state variable {}", var),
+            AgentError::InvalidFormat => f.write_str("invalid printf format"),
+            AgentError::TooManyInstructions => f.write_str("too many instructions executed"),
+        }
+    }
+}
+
+/// Evaluates agent expressions against a target.
+pub(crate) struct Agent<'a, T: StubCalls + 'a> {
+    target: &'a mut T,
+    /// The raw register contents and their layout, read when first needed.
+    regs: Option<(Vec<u8>, Vec<RegDesc>)>,
+    /// Text printed by `printf` instructions.
+    output: String,
+    /// Trace state variables accessible to the bytecode, if any.
+    variables: Option<&'a mut [TraceVariable]>,
+    /// Memory blocks collected by `trace` instructions.
+    memory: Vec<(u64, Vec<u8>)>,
+    /// Values of trace state variables collected by `tracev` instructions.
+    collected_variables: Vec<(u32, i64)>,
+}
+
+impl<'a, T: StubCalls> Agent<'a, T> {
+    pub fn new(target: &'a mut T) -> Self {
+        Agent {
+            target,
+            regs: None,
+            output: String::new(),
+            variables: None,
+            memory: Vec::new(),
+            collected_variables: Vec::new(),
+        }
+    }
+
+    /// Makes the trace state variables `variables` accessible to the
+    /// bytecode.
+    pub fn with_variables(mut self, variables: &'a mut [TraceVariable]) -> Self {
+        self.variables = Some(variables);
+        self
+    }
+
+    /// Returns the text printed since the last call.
+    pub fn take_output(&mut self) -> String {
+        mem::take(&mut self.output)
+    }
+
+    /// Returns the memory blocks collected since the last call.
+    pub fn take_memory(&mut self) -> Vec<(u64, Vec<u8>)> {
+        mem::take(&mut self.memory)
+    }
+
+    /// Returns the trace state variable values collected since the last call.
+    pub fn take_variables(&mut self) -> Vec<(u32, i64)> {
+        mem::take(&mut self.collected_variables)
+    }
+
+    /// Reads `len` bytes of target memory at `addr` and adds them to the
+    /// collected memory.
+    pub fn collect(&mut self, addr: u64, len: u64) -> Result<(), AgentError> {
+        // anything larger wouldn't fit into the trace buffer anyway
+        let len = len.min(TRACE_BUFFER_SIZE as u64);
+        let bytes = (0..len)
+            .map(|i| self.read_byte(addr, i))
+            .collect::<Result<_, _>>()?;
+        self.memory.push((addr, bytes));
+        Ok(())
+    }
+
+    /// Runs the bytecode `code` and returns the value on top of the stack
+    /// when it ends.
+    pub fn eval(&mut self, code: &[u8]) -> Result<u64, AgentError> {
+        let mut stack = Stack(Vec::new());
+        let mut pc = 0;
+
+        for _ in 0..INSTRUCTION_LIMIT {
+            let op = *code.get(pc).ok_or(AgentError::Truncated)?;
+            pc += 1;
+            match op {
+                // arithmetic
+                0x02 => stack.binary(|a, b| Ok(a.wrapping_add(b)))?,
+                0x03 => stack.binary(|a, b| Ok(a.wrapping_sub(b)))?,
+                0x04 => stack.binary(|a, b| Ok(a.wrapping_mul(b)))?,
+                0x05 => stack.binary(|a, b| match b {
+                    0 => Err(AgentError::DivisionByZero),
+                    _ => Ok((a as i64).wrapping_div(b as i64) as u64),
+                })?,
+                0x06 => stack.binary(|a, b| a.checked_div(b).ok_or(AgentError::DivisionByZero))?,
+                0x07 => stack.binary(|a, b| match b {
+                    0 => Err(AgentError::DivisionByZero),
+                    _ => Ok((a as i64).wrapping_rem(b as i64) as u64),
+                })?,
+                0x08 => stack.binary(|a, b| a.checked_rem(b).ok_or(AgentError::DivisionByZero))?,
+                0x09 => stack.binary(|a, b| Ok(if b < 64 { a << b } else { 0 }))?,
+                0x0a => stack.binary(|a, b| Ok(((a as i64) >> b.min(63)) as u64))?,
+                0x0b => stack.binary(|a, b| Ok(if b < 64 { a >> b } else { 0 }))?,
+                // trace
+                0x0c => {
+                    let size = stack.pop()?;
+                    let addr = stack.pop()?;
+                    self.collect(addr, size)?;
+                }
+                // trace_quick n
+                0x0d => {
+                    let size = immediate(code, &mut pc, 1)?;
+                    let addr = stack.pick(0)?;
+                    self.collect(addr, size)?;
+                }
+                // logic and comparisons
+                0x0e => {
+                    let a = stack.pop()?;
+                    stack.push((a == 0) as u64)?;
+                }
+                0x0f => stack.binary(|a, b| Ok(a & b))?,
+                0x10 => stack.binary(|a, b| Ok(a | b))?,
+                0x11 => stack.binary(|a, b| Ok(a ^ b))?,
+                0x12 => {
+                    let a = stack.pop()?;
+                    stack.push(!a)?;
+                }
+                0x13 => stack.binary(|a, b| Ok((a == b) as u64))?,
+                0x14 => stack.binary(|a, b| Ok(((a as i64) < (b as i64)) as u64))?,
+                0x15 => stack.binary(|a, b| Ok((a < b) as u64))?,
+                // ext n
+                0x16 => {
+                    let bits = immediate(code, &mut pc, 1)?;
+                    let a = stack.pop()?;
+                    stack.push(sign_extend(a, bits))?;
+                }
+                // ref8 / ref16 / ref32 / ref64
+                0x17..=0x1a => {
+                    let size = 1 << (op - 0x17);
+                    let addr = stack.pop()?;
+                    let value = read_uint(self.target, addr, size).map_err(|_| AgentError::Memory(addr))?;
+                    stack.push(value)?;
+                }
+                // if_goto / goto
+                0x20 => {
+                    let target = immediate(code, &mut pc, 2)?;
+                    if stack.pop()? != 0 {
+                        pc = target as usize;
+                    }
+                }
+                0x21 => pc = immediate(code, &mut pc, 2)? as usize,
+                // const8 / const16 / const32 / const64
+                0x22..=0x25 => {
+                    let size = 1 << (op - 0x22);
+                    let value = immediate(code, &mut pc, size)?;
+                    stack.push(value)?;
+                }
+                // reg n
+                0x26 => {
+                    let reg = immediate(code, &mut pc, 2)? as u16;
+                    let value = self.register(reg)?;
+                    stack.push(value)?;
+                }
+                // end (commands like `printf` leave the stack empty)
+                0x27 => return Ok(stack.0.pop().unwrap_or(0)),
+                // stack manipulation
+                0x28 => {
+                    let a = stack.pick(0)?;
+                    stack.push(a)?;
+                }
+                0x29 => {
+                    stack.pop()?;
+                }
+                // zero_ext n
+                0x2a => {
+                    let bits = immediate(code, &mut pc, 1)?;
+                    let a = stack.pop()?;
+                    stack.push(if bits < 64 { a & ((1 << bits) - 1) } else { a })?;
+                }
+                0x2b => {
+                    let b = stack.pop()?;
+                    let a = stack.pop()?;
+                    stack.push(b)?;
+                    stack.push(a)?;
+                }
+                // getv n
+                0x2c => {
+                    let var = immediate(code, &mut pc, 2)? as u32;
+                    let value = self.variable(var)?.value;
+                    stack.push(value as u64)?;
+                }
+                // setv n
+                0x2d => {
+                    let var = immediate(code, &mut pc, 2)? as u32;
+                    let value = stack.pick(0)?;
+                    self.variable(var)?.value = value as i64;
+                }
+                // tracev n
+                0x2e => {
+                    let var = immediate(code, &mut pc, 2)? as u32;
+                    let value = self.variable(var)?.value;
+                    self.collected_variables.push((var, value));
+                }
+                // tracenz: collects a string up to and including its NUL
+                0x2f => {
+                    let size = stack.pop()?;
+                    let addr = stack.pop()?;
+                    let mut len = 0;
+                    while len < size.min(TRACE_BUFFER_SIZE as u64) {
+                        let byte = self.read_byte(addr, len)?;
+                        len += 1;
+                        if byte == 0 {
+                            break;
+                        }
+                    }
+                    self.collect(addr, len)?;
+                }
+                // trace16 n
+                0x30 => {
+                    let size = immediate(code, &mut pc, 2)?;
+                    let addr = stack.pick(0)?;
+                    self.collect(addr, size)?;
+                }
+                // pick n
+                0x32 => {
+                    let n = immediate(code, &mut pc, 1)?;
+                    let a = stack.pick(n as usize)?;
+                    stack.push(a)?;
+                }
+                // rot: a b c => c a b
+                0x33 => {
+                    let c = stack.pop()?;
+                    let b = stack.pop()?;
+                    let a = stack.pop()?;
+                    stack.push(c)?;
+                    stack.push(a)?;
+                    stack.push(b)?;
+                }
+                // printf nargs len16 format
+                0x34 => {
+                    let nargs = immediate(code, &mut pc, 1)?;
+                    let len = immediate(code, &mut pc, 2)? as usize;
+                    let format = code.get(pc..pc + len).ok_or(AgentError::Truncated)?;
+                    pc += len;
+
+                    // the function and channel are only meaningful to
+                    // in-process agents
+                    stack.pop()?;
+                    stack.pop()?;
+                    let args = (0..nargs).map(|_| stack.pop()).collect::<Result<Vec<_>, _>>()?;
+                    match format.split_last() {
+                        Some((0, format)) => self.printf(format, &args)?,
+                        _ => return Err(AgentError::InvalidFormat),
+                    }
+                }
+                _ => return Err(AgentError::InvalidOpcode(op)),
+            }
+        }
+
+        Err(AgentError::TooManyInstructions)
+    }
+
+    /// Returns the value of register number `reg`, numbered in the order of
+    /// the target description.
+    pub fn register(&mut self, reg: u16) -> Result<u64, AgentError> {
+        if self.regs.is_none() {
+            let regs = self.target.read_registers();
+            let descs = T::Target::describe(&regs);
+            self.regs = Some((raw_registers::<T::Target>(&regs), descs));
+        }
+
+        let (ref raw, ref descs) = *self.regs.as_ref().unwrap();
+        let desc = descs.get(reg as usize).ok_or(AgentError::Register(reg))?;
+        let size = desc.bitsize as usize / 8;
+        if size == 0 || size > 8 {
+            return Err(AgentError::Register(reg));
+        }
+
+        let offset: usize = descs[..reg as usize].iter().map(|desc| desc.bitsize as usize / 8).sum();
+        let bytes = raw.get(offset..offset + size).ok_or(AgentError::Register(reg))?;
+        Ok(<<T::Target as TargetDesc>::Endianness as ByteOrder>::read_uint(bytes, size))
+    }
+
+    /// Returns trace state variable number `var`.
+    fn variable(&mut self, var: u32) -> Result<&mut TraceVariable, AgentError> {
+        self.variables.as_mut()
+            .and_then(|variables| variables.iter_mut().find(|v| v.number == var))
+            .ok_or(AgentError::Variable(var))
+    }
+
+    /// Formats `args` according to the C format string `format` and appends
+    /// the result to the output.
+    ///
+    /// GDB sends the format as it was written in the source, so its escape
+    /// sequences are replaced before formatting.
+    fn printf(&mut self, format: &[u8], args: &[u64]) -> Result<(), AgentError> {
+        let format = &*unescape_c(format)?;
+        let mut args = args.iter().cloned();
+        let mut i = 0;
+        while i < format.len() {
+            let start = i;
+            while i < format.len() && format[i] != b'%' {
+                i += 1;
+            }
+            self.output.push_str(&String::from_utf8_lossy(&format[start..i]));
+            if i == format.len() {
+                break;
+            }
+
+            let spec = Spec::parse(format, &mut i)?;
+            let text = match spec.conversion {
+                b'%' => {
+                    self.output.push('%');
+                    continue;
+                }
+                b'c' => (args.next().ok_or(AgentError::InvalidFormat)? as u8 as char).to_string(),
+                b's' => {
+                    let addr = args.next().ok_or(AgentError::InvalidFormat)?;
+                    let limit = spec.precision.unwrap_or(STRING_LIMIT).min(STRING_LIMIT);
+                    self.read_string(addr, limit)?
+                }
+                b'p' => format!("0x{:x}", args.next().ok_or(AgentError::InvalidFormat)?),
+                _ => spec.format_int(args.next().ok_or(AgentError::InvalidFormat)?)?,
+            };
+            spec.pad(&text, &mut self.output);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the byte of target memory at `offset` from `addr`.
+    fn read_byte(&mut self, addr: u64, offset: u64) -> Result<u8, AgentError> {
+        let addr = addr.checked_add(offset).ok_or(AgentError::Memory(addr))?;
+        self.target.read_mem(addr).map_err(|_| AgentError::Memory(addr))
+    }
+
+    /// Reads a NUL-terminated string of at most `limit` bytes from target
+    /// memory.
+    fn read_string(&mut self, addr: u64, limit: usize) -> Result<String, AgentError> {
+        let mut bytes = Vec::new();
+        for i in 0..limit as u64 {
+            match self.read_byte(addr, i)? {
+                0 => break,
+                byte => bytes.push(byte),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// A conversion specification of a `printf` format string.
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    /// Size of the argument in bits.
+    bits: u64,
+    conversion: u8,
+}
+
+impl Spec {
+    /// Parses the specification starting at the `%` at `format[*i]`.
+    fn parse(format: &[u8], i: &mut usize) -> Result<Self, AgentError> {
+        let mut spec = Spec {
+            left: false,
+            zero: false,
+            plus: false,
+            space: false,
+            alternate: false,
+            width: 0,
+            precision: None,
+            bits: 32,
+            conversion: 0,
+        };
+
+        *i += 1;
+        while let Some(&flag) = format.get(*i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => break,
+            }
+            *i += 1;
+        }
+        spec.width = number(format, i);
+        if format.get(*i) == Some(&b'.') {
+            *i += 1;
+            spec.precision = Some(number(format, i));
+        }
+        while let Some(&len) = format.get(*i) {
+            spec.bits = match len {
+                b'h' if spec.bits == 16 => 8,
+                b'h' => 16,
+                b'l' | b'j' | b'z' | b't' => 64,
+                _ => break,
+            };
+            *i += 1;
+        }
+
+        spec.conversion = *format.get(*i).ok_or(AgentError::InvalidFormat)?;
+        *i += 1;
+        Ok(spec)
+    }
+
+    /// Formats an integer argument, without padding it to the width.
+    fn format_int(&self, value: u64) -> Result<String, AgentError> {
+        let unsigned = if self.bits < 64 { value & ((1 << self.bits) - 1) } else { value };
+        let (prefix, digits) = match self.conversion {
+            b'd' | b'i' => {
+                let signed = sign_extend(value, self.bits) as i64;
+                let sign = if signed < 0 {
+                    "-"
+                } else if self.plus {
+                    "+"
+                } else if self.space {
+                    " "
+                } else {
+                    ""
+                };
+                (sign, signed.unsigned_abs().to_string())
+            }
+            b'u' => ("", unsigned.to_string()),
+            b'x' => (if self.alternate && unsigned != 0 { "0x" } else { "" }, format!("{:x}", unsigned)),
+            b'X' => (if self.alternate && unsigned != 0 { "0X" } else { "" }, format!("{:X}", unsigned)),
+            b'o' => (if self.alternate { "0" } else { "" }, format!("{:o}", unsigned)),
+            _ => return Err(AgentError::InvalidFormat),
+        };
+
+        let mut text = String::from(prefix);
+        if let Some(precision) = self.precision {
+            text.extend(iter::repeat_n('0', precision.saturating_sub(digits.len())));
+        } else if self.zero && !self.left {
+            let len = prefix.len() + digits.len();
+            text.extend(iter::repeat_n('0', self.width.saturating_sub(len)));
+        }
+        text.push_str(&digits);
+        Ok(text)
+    }
+
+    /// Appends `text` to `out`, padded with spaces to the field width.
+    fn pad(&self, text: &str, out: &mut String) {
+        let padding = self.width.saturating_sub(text.chars().count());
+        if !self.left {
+            out.extend(iter::repeat_n(' ', padding));
+        }
+        out.push_str(text);
+        if self.left {
+            out.extend(iter::repeat_n(' ', padding));
+        }
+    }
+}
+
+/// Parses a decimal number at `format[*i]`, returning 0 if there is none.
+fn number(format: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    while let Some(digit @ b'0'..=b'9') = format.get(*i).cloned() {
+        value = value * 10 + (digit - b'0') as usize;
+        *i += 1;
+    }
+    value
+}
+
+/// The evaluation stack.
+struct Stack(Vec<u64>);
+
+impl Stack {
+    fn push(&mut self, value: u64) -> Result<(), AgentError> {
+        if self.0.len() >= STACK_LIMIT {
+            return Err(AgentError::StackOverflow);
+        }
+        self.0.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u64, AgentError> {
+        self.0.pop().ok_or(AgentError::StackUnderflow)
+    }
+
+    /// Returns the `n`-th value from the top, where 0 is the top.
+    fn pick(&self, n: usize) -> Result<u64, AgentError> {
+        let len = self.0.len();
+        if n >= len {
+            return Err(AgentError::StackUnderflow);
+        }
+        Ok(self.0[len - 1 - n])
+    }
+
+    /// Replaces the top two values `a b` (with `b` on top) by `f(a, b)`.
+    fn binary<F>(&mut self, f: F) -> Result<(), AgentError>
+    where F: FnOnce(u64, u64) -> Result<u64, AgentError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        self.push(f(a, b)?)
+    }
+}
+
+/// Replaces the escape sequences of a C string literal.
+fn unescape_c(s: &[u8]) -> Result<Vec<u8>, AgentError> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let c = s[i];
+        i += 1;
+        if c != b'\\' {
+            result.push(c);
+            continue;
+        }
+
+        let c = *s.get(i).ok_or(AgentError::InvalidFormat)?;
+        i += 1;
+        let byte = match c {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'e' => 0x1b,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            b'\\' | b'\'' | b'"' | b'?' => c,
+            b'0'..=b'7' => {
+                // up to three octal digits
+                let mut value = u32::from(c - b'0');
+                for _ in 0..2 {
+                    match s.get(i) {
+                        Some(&d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            i += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                value as u8
+            }
+            _ => return Err(AgentError::InvalidFormat),
+        };
+        result.push(byte);
+    }
+    Ok(result)
+}
+
+/// Reads a big-endian immediate operand of `size` bytes following an opcode.
+fn immediate(code: &[u8], pc: &mut usize, size: usize) -> Result<u64, AgentError> {
+    let bytes = code.get(*pc..*pc + size).ok_or(AgentError::Truncated)?;
+    *pc += size;
+    Ok(BigEndian::read_uint(bytes, size))
+}
+
+/// Sign-extends the lowest `bits` bits of `value`.
+fn sign_extend(value: u64, bits: u64) -> u64 {
+    if bits == 0 || bits >= 64 {
+        return value;
+    }
+    let shift = 64 - bits;
+    (((value << shift) as i64) >> shift) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use targets::{Dynamic, DynamicRegisters};
+    use {Console, StopReason};
+    use byteorder::LittleEndian;
+
+    /// A target whose 16 bytes of memory repeat over the whole address space.
+    struct Mock {
+        regs: DynamicRegisters,
+        mem: Vec<u8>,
+    }
+
+    impl Mock {
+        fn new() -> Self {
+            let mut regs = DynamicRegisters::new(None, "org.gnu.gdb.arm.core", vec![
+                RegDesc::new("r0", 32, "uint32"),
+                RegDesc::new("pc", 32, "code_ptr"),
+            ]);
+            regs.get_mut(0).copy_from_slice(&[0x44, 0x33, 0x22, 0x11]);
+            Mock { regs, mem: b"hi\0\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d".to_vec() }
+        }
+    }
+
+    impl StubCalls for Mock {
+        type Target = Dynamic<LittleEndian>;
+
+        fn read_registers(&mut self) -> DynamicRegisters { self.regs.clone() }
+        fn write_registers(&mut self, regs: DynamicRegisters) { self.regs = regs; }
+        fn read_mem(&mut self, addr: u64) -> Result<u8, ()> { Ok(self.mem[addr as usize % self.mem.len()]) }
+        fn write_mem(&mut self, _addr: u64, _byte: u8) -> Result<(), ()> { Err(()) }
+        fn cont(&mut self, _console: &mut Console) -> StopReason { unreachable!() }
+        fn step(&mut self, _console: &mut Console) -> StopReason { unreachable!() }
+    }
+
+    fn eval(code: &[u8]) -> Result<u64, AgentError> {
+        Agent::new(&mut Mock::new()).eval(code)
+    }
+
+    #[test]
+    fn eval_expressions() {
+        // 2 + 3
+        assert_eq!(eval(&[0x22, 2, 0x22, 3, 0x02, 0x27]), Ok(5));
+        // r0
+        assert_eq!(eval(&[0x26, 0, 0, 0x27]), Ok(0x11223344));
+        // *(uint32_t *)3
+        assert_eq!(eval(&[0x22, 3, 0x19, 0x27]), Ok(0x04030201));
+        // if (0) goto end; 1
+        assert_eq!(eval(&[0x22, 0, 0x20, 0, 7, 0x22, 1, 0x27]), Ok(1));
+        assert_eq!(eval(&[0x06, 0x27]), Err(AgentError::StackUnderflow));
+        assert_eq!(eval(&[0x22, 1, 0x22, 0, 0x05, 0x27]), Err(AgentError::DivisionByZero));
+        assert_eq!(eval(&[0x22, 1]), Err(AgentError::Truncated));
+        assert_eq!(eval(&[0x26, 0, 2, 0x27]), Err(AgentError::Register(2)));
+    }
+
+    #[test]
+    fn eval_limits() {
+        // reading past the end of the address space
+        let code = [0x25, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0x19, 0x27];
+        assert_eq!(eval(&code), Err(AgentError::Memory(!1)));
+        let code = [0x25, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x22, 8, 0x2f, 0x27];
+        assert_eq!(eval(&code), Err(AgentError::Memory(!0)));
+        // goto 0
+        assert_eq!(eval(&[0x21, 0, 0]), Err(AgentError::TooManyInstructions));
+    }
+
+    #[test]
+    fn printf() {
+        let mut target = Mock::new();
+        let mut agent = Agent::new(&mut target);
+        let format = b"%s: %#06x\\t\\\"%-3d\\\"\\101\\n\0";
+        let mut code = vec![0x22, 42, 0x26, 0, 0, 0x22, 0, 0x22, 0, 0x22, 0, 0x34, 3, 0, format.len() as u8];
+        code.extend_from_slice(format);
+        code.push(0x27);
+
+        assert_eq!(agent.eval(&code), Ok(0));
+        assert_eq!(agent.take_output(), "hi: 0x11223344\t\"42 \"A\n");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape_c(br"a\\\a\e\'\?\0\177\1234"), Ok(b"a\\\x07\x1b'?\0\x7fS4".to_vec()));
+        assert_eq!(unescape_c(br"\q"), Err(AgentError::InvalidFormat));
+        assert_eq!(unescape_c(br"\"), Err(AgentError::InvalidFormat));
+    }
+}
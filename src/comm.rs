@@ -8,7 +8,7 @@ use std::io::prelude::*;
 /// and `Write` (eg. `TcpStream`).
 pub trait Comm {
     /// Error type returned when reading or writing fails.
-    type Error: Into<Box<error::Error + Send + Sync>>;
+    type Error: Into<Box<dyn error::Error + Send + Sync>>;
 
     /// Read a byte from the connected debugger.
     fn read(&mut self) -> Result<u8, Self::Error>;
@@ -50,6 +50,22 @@ pub trait Comm {
 
         Ok(())
     }
+
+    /// Writes all bytes in `data` using the binary encoding, escaping bytes
+    /// that have a special meaning in the protocol.
+    fn write_all_escaped(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        for b in data {
+            match *b {
+                b'#' | b'$' | b'}' | b'*' => {
+                    self.write(b'}')?;
+                    self.write(*b ^ 0x20)?;
+                }
+                b => self.write(b)?,
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<T> Comm for T
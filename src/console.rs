@@ -0,0 +1,52 @@
+use {Comm, Error, ResponseWriter};
+
+use std::io;
+
+/// Output channel to the debugger's console.
+///
+/// Everything written to the console is sent to GDB as `O` packets and shown
+/// to the user.
+pub struct Console<'a> {
+    sink: &'a mut dyn OutputSink,
+}
+
+impl<'a> Console<'a> {
+    pub(crate) fn new(sink: &'a mut dyn OutputSink) -> Self {
+        Console { sink }
+    }
+
+    /// Sends `text` to the debugger's console.
+    pub fn print(&mut self, text: &str) -> Result<(), Error> {
+        self.sink.output(text.as_bytes())
+    }
+}
+
+impl<'a> io::Write for Console<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sink.output(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Object-safe interface for sending console output over a `Comm`.
+pub(crate) trait OutputSink {
+    fn output(&mut self, data: &[u8]) -> Result<(), Error>;
+}
+
+impl<C: Comm> OutputSink for C {
+    fn output(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            // an empty `O` packet would be mistaken for an `OK`
+            return Ok(());
+        }
+
+        let mut resp = ResponseWriter::new(self)?;
+        resp.write(b'O').map_err(Error::comm)?;
+        resp.write_all_hex(data).map_err(Error::comm)?;
+        resp.finish()
+    }
+}
@@ -5,7 +5,7 @@ use std::fmt;
 #[derive(Debug)]
 pub enum Error {
     /// Error during communication.
-    CommError(Box<error::Error + Send + Sync>),
+    CommError(Box<dyn error::Error + Send + Sync>),
 
     /// An unexpected byte was received.
     Unexpected {
@@ -34,12 +34,22 @@ pub enum Error {
     ///
     /// This is not a fatal error and just indicates that the debugger closed
     /// the connection. It is not returned by `GdbStub::poll`, which instead
-    /// returns `Ok(())` when the target is killed.
+    /// returns `Ok(Disconnect::Killed)` when the target is killed. In extended
+    /// mode, the stub keeps serving the debugger after a kill instead.
     Killed,
+
+    /// The debugger has detached from the target.
+    ///
+    /// Prior to returning this error, the library will call
+    /// `StubCalls::detach`.
+    ///
+    /// Like `Killed`, this is not a fatal error and is not returned by
+    /// `GdbStub::poll`, which instead returns `Ok(Disconnect::Detached)`.
+    Detached,
 }
 
 impl Error {
-    pub(crate) fn comm<E>(e: E) -> Self where E: Into<Box<error::Error + Send + Sync>> {
+    pub(crate) fn comm<E>(e: E) -> Self where E: Into<Box<dyn error::Error + Send + Sync>> {
         Error::CommError(e.into())
     }
 
@@ -57,6 +67,7 @@ impl fmt::Display for Error {
             Error::Checksum { received, computed } => write!(f, "incorrect checksum, got {:02X}, expected {:02X}", received, computed),
             Error::Nack => write!(f, "debugger did not acknowledge answer"),
             Error::Killed => write!(f, "the target process has been killed"),
+            Error::Detached => write!(f, "the debugger detached from the target"),
         }
     }
 }
@@ -0,0 +1,101 @@
+//! The File-I/O remote protocol extension.
+//!
+//! This allows the target program to perform system calls like `open`, `read`
+//! or `write` on the host running GDB, which is useful for semihosting. The
+//! target requests an operation by returning `StopReason::FileIo` from
+//! `StubCalls::cont` or `StubCalls::step`. GDB accesses the buffers in target
+//! memory while performing the operation, after which the target is resumed
+//! and receives the result through `StubCalls::file_io_reply`.
+
+use hostio::{Errno, OpenFlags};
+
+use std::fmt;
+
+/// A buffer or string in target memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetBuf {
+    /// Address of the first byte.
+    pub addr: u64,
+    /// Length in bytes. For strings, this includes the terminating NUL byte.
+    pub len: u64,
+}
+
+impl TargetBuf {
+    pub fn new(addr: u64, len: u64) -> Self {
+        Self { addr, len }
+    }
+}
+
+impl fmt::Display for TargetBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:x}/{:x}", self.addr, self.len)
+    }
+}
+
+/// A system call the target program wants GDB to perform on the host.
+#[derive(Debug, Copy, Clone)]
+pub enum FileIoRequest {
+    /// `open(path, flags, mode)`
+    Open { path: TargetBuf, flags: OpenFlags, mode: u32 },
+    /// `close(fd)`
+    Close { fd: u32 },
+    /// `read(fd, buf, count)`
+    Read { fd: u32, buf: u64, count: u64 },
+    /// `write(fd, buf, count)`
+    Write { fd: u32, buf: u64, count: u64 },
+    /// `lseek(fd, offset, whence)`, where `whence` is 0 (`SEEK_SET`), 1
+    /// (`SEEK_CUR`) or 2 (`SEEK_END`).
+    Lseek { fd: u32, offset: i64, whence: u32 },
+    /// `rename(old, new)`
+    Rename { old: TargetBuf, new: TargetBuf },
+    /// `unlink(path)`
+    Unlink { path: TargetBuf },
+    /// `stat(path, buf)`, where `buf` points to a 64-byte big-endian
+    /// structure (see `hostio::FileStat`).
+    Stat { path: TargetBuf, buf: u64 },
+    /// `fstat(fd, buf)`
+    Fstat { fd: u32, buf: u64 },
+    /// `gettimeofday(tv, tz)`
+    GetTimeOfDay { tv: u64, tz: u64 },
+    /// `isatty(fd)`
+    IsATty { fd: u32 },
+    /// `system(cmd)`
+    System { cmd: TargetBuf },
+}
+
+impl fmt::Display for FileIoRequest {
+    /// Formats the request as the body of an `F` packet.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileIoRequest::Open { path, flags, mode } => write!(f, "Fopen,{},{:x},{:x}", path, flags.0, mode),
+            FileIoRequest::Close { fd } => write!(f, "Fclose,{:x}", fd),
+            FileIoRequest::Read { fd, buf, count } => write!(f, "Fread,{:x},{:x},{:x}", fd, buf, count),
+            FileIoRequest::Write { fd, buf, count } => write!(f, "Fwrite,{:x},{:x},{:x}", fd, buf, count),
+            FileIoRequest::Lseek { fd, offset, whence } => {
+                let sign = if offset < 0 { "-" } else { "" };
+                write!(f, "Flseek,{:x},{}{:x},{:x}", fd, sign, offset.unsigned_abs(), whence)
+            }
+            FileIoRequest::Rename { old, new } => write!(f, "Frename,{},{}", old, new),
+            FileIoRequest::Unlink { path } => write!(f, "Funlink,{}", path),
+            FileIoRequest::Stat { path, buf } => write!(f, "Fstat,{},{:x}", path, buf),
+            FileIoRequest::Fstat { fd, buf } => write!(f, "Ffstat,{:x},{:x}", fd, buf),
+            FileIoRequest::GetTimeOfDay { tv, tz } => write!(f, "Fgettimeofday,{:x},{:x}", tv, tz),
+            FileIoRequest::IsATty { fd } => write!(f, "Fisatty,{:x}", fd),
+            FileIoRequest::System { cmd } => write!(f, "Fsystem,{}", cmd),
+        }
+    }
+}
+
+/// The result of a `FileIoRequest`, as sent by GDB.
+#[derive(Debug, Copy, Clone)]
+pub struct FileIoReply {
+    /// The return value of the system call (eg. the number of bytes read).
+    pub result: i64,
+    /// The error that occurred, if the call failed.
+    pub errno: Option<Errno>,
+    /// Whether the user pressed Ctrl-C during the call.
+    ///
+    /// If this is set, the stub will report the target as interrupted instead
+    /// of resuming it.
+    pub ctrl_c: bool,
+}
@@ -0,0 +1,447 @@
+//! Host I/O (`vFile`) support.
+//!
+//! GDB uses these operations to access files on the target side, for example
+//! to fetch the executable and shared libraries for symbol loading, or for
+//! `remote get` and `remote put`. The types in this module follow the
+//! encodings of GDB's File-I/O protocol.
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// An error number as defined by GDB's File-I/O protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Errno(pub u32);
+
+impl Errno {
+    pub const EPERM: Errno = Errno(1);
+    pub const ENOENT: Errno = Errno(2);
+    pub const EINTR: Errno = Errno(4);
+    pub const EBADF: Errno = Errno(9);
+    pub const EACCES: Errno = Errno(13);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EBUSY: Errno = Errno(16);
+    pub const EEXIST: Errno = Errno(17);
+    pub const ENODEV: Errno = Errno(19);
+    pub const ENOTDIR: Errno = Errno(20);
+    pub const EISDIR: Errno = Errno(21);
+    pub const EINVAL: Errno = Errno(22);
+    pub const ENFILE: Errno = Errno(23);
+    pub const EMFILE: Errno = Errno(24);
+    pub const EFBIG: Errno = Errno(27);
+    pub const ENOSPC: Errno = Errno(28);
+    pub const ESPIPE: Errno = Errno(29);
+    pub const EROFS: Errno = Errno(30);
+    pub const ENAMETOOLONG: Errno = Errno(91);
+    pub const EUNKNOWN: Errno = Errno(9999);
+}
+
+impl From<io::Error> for Errno {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Errno::ENOENT,
+            io::ErrorKind::PermissionDenied => Errno::EACCES,
+            io::ErrorKind::AlreadyExists => Errno::EEXIST,
+            io::ErrorKind::InvalidInput => Errno::EINVAL,
+            io::ErrorKind::Interrupted => Errno::EINTR,
+            io::ErrorKind::NotADirectory => Errno::ENOTDIR,
+            io::ErrorKind::IsADirectory => Errno::EISDIR,
+            io::ErrorKind::ReadOnlyFilesystem => Errno::EROFS,
+            io::ErrorKind::FileTooLarge => Errno::EFBIG,
+            io::ErrorKind::StorageFull => Errno::ENOSPC,
+            io::ErrorKind::NotSeekable => Errno::ESPIPE,
+            io::ErrorKind::InvalidFilename => Errno::ENAMETOOLONG,
+            _ => Errno::EUNKNOWN,
+        }
+    }
+}
+
+/// Flags passed when opening a file, as defined by GDB's File-I/O protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OpenFlags(pub u32);
+
+impl OpenFlags {
+    pub const RDONLY: u32 = 0x0;
+    pub const WRONLY: u32 = 0x1;
+    pub const RDWR: u32 = 0x2;
+    pub const APPEND: u32 = 0x8;
+    pub const CREAT: u32 = 0x200;
+    pub const TRUNC: u32 = 0x400;
+    pub const EXCL: u32 = 0x800;
+
+    /// Returns whether the file should be opened for reading.
+    pub fn read(&self) -> bool {
+        self.0 & 0x3 != OpenFlags::WRONLY
+    }
+
+    /// Returns whether the file should be opened for writing.
+    pub fn write(&self) -> bool {
+        self.0 & 0x3 != OpenFlags::RDONLY
+    }
+
+    /// Returns whether `flag` (one of the constants defined on this type) is
+    /// set.
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag != 0
+    }
+
+    /// Converts the flags to `OpenOptions` for opening a host file.
+    pub fn to_open_options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options
+            .read(self.read())
+            .write(self.write())
+            .append(self.contains(OpenFlags::APPEND))
+            .truncate(self.contains(OpenFlags::TRUNC));
+        if self.contains(OpenFlags::EXCL) {
+            options.create_new(self.contains(OpenFlags::CREAT));
+        } else {
+            options.create(self.contains(OpenFlags::CREAT));
+        }
+        options
+    }
+}
+
+/// File mode bits, as defined by GDB's File-I/O protocol.
+pub mod mode {
+    pub const S_IFREG: u32 = 0o100000;
+    pub const S_IFDIR: u32 = 0o40000;
+    pub const S_IRUSR: u32 = 0o400;
+    pub const S_IWUSR: u32 = 0o200;
+    pub const S_IXUSR: u32 = 0o100;
+    pub const S_IRGRP: u32 = 0o40;
+    pub const S_IWGRP: u32 = 0o20;
+    pub const S_IXGRP: u32 = 0o10;
+    pub const S_IROTH: u32 = 0o4;
+    pub const S_IWOTH: u32 = 0o2;
+    pub const S_IXOTH: u32 = 0o1;
+}
+
+/// File information returned by `fstat`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FileStat {
+    pub dev: u32,
+    pub ino: u32,
+    /// File type and permissions (see the `mode` module).
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u32,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+}
+
+impl FileStat {
+    /// Size of the encoded structure in bytes.
+    pub(crate) const SIZE: usize = 64;
+
+    /// Creates a `FileStat` from the metadata of a host file.
+    pub fn from_metadata(meta: &fs::Metadata) -> Self {
+        let mut perms = mode::S_IRUSR | mode::S_IRGRP | mode::S_IROTH;
+        if !meta.permissions().readonly() {
+            perms |= mode::S_IWUSR;
+        }
+        let time = |time: io::Result<::std::time::SystemTime>| time.ok()
+            .and_then(|time| time.duration_since(::std::time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as u32)
+            .unwrap_or(0);
+
+        FileStat {
+            mode: perms | if meta.is_dir() { mode::S_IFDIR } else { mode::S_IFREG },
+            nlink: 1,
+            size: meta.len(),
+            blksize: 4096,
+            blocks: meta.len().div_ceil(512),
+            atime: time(meta.accessed()),
+            mtime: time(meta.modified()),
+            ctime: time(meta.created()),
+            ..FileStat::default()
+        }
+    }
+
+    /// Encodes the structure in the big-endian format GDB expects.
+    pub(crate) fn encode(&self) -> [u8; FileStat::SIZE] {
+        let mut buf = [0; FileStat::SIZE];
+        BigEndian::write_u32(&mut buf[0..], self.dev);
+        BigEndian::write_u32(&mut buf[4..], self.ino);
+        BigEndian::write_u32(&mut buf[8..], self.mode);
+        BigEndian::write_u32(&mut buf[12..], self.nlink);
+        BigEndian::write_u32(&mut buf[16..], self.uid);
+        BigEndian::write_u32(&mut buf[20..], self.gid);
+        BigEndian::write_u32(&mut buf[24..], self.rdev);
+        BigEndian::write_u64(&mut buf[28..], self.size);
+        BigEndian::write_u64(&mut buf[36..], self.blksize);
+        BigEndian::write_u64(&mut buf[44..], self.blocks);
+        BigEndian::write_u32(&mut buf[52..], self.atime);
+        BigEndian::write_u32(&mut buf[56..], self.mtime);
+        BigEndian::write_u32(&mut buf[60..], self.ctime);
+        buf
+    }
+}
+
+/// File operations on the target side, requested by the debugger using
+/// `vFile` packets.
+///
+/// File names are the paths as seen by the target. File descriptors are
+/// chosen by the implementation.
+pub trait HostIo {
+    /// Opens a file and returns a new file descriptor.
+    ///
+    /// `mode` contains the permissions used when creating a file.
+    fn open(&mut self, filename: &str, flags: OpenFlags, mode: u32) -> Result<u32, Errno>;
+
+    /// Closes a file descriptor.
+    fn close(&mut self, fd: u32) -> Result<(), Errno>;
+
+    /// Reads up to `buf.len()` bytes starting at `offset` from an open file.
+    ///
+    /// Returns the number of bytes read, which is 0 at the end of the file.
+    fn pread(&mut self, fd: u32, buf: &mut [u8], offset: u64) -> Result<usize, Errno>;
+
+    /// Writes `data` starting at `offset` to an open file.
+    ///
+    /// Returns the number of bytes written.
+    fn pwrite(&mut self, _fd: u32, _data: &[u8], _offset: u64) -> Result<usize, Errno> {
+        Err(Errno::EROFS)
+    }
+
+    /// Returns information about an open file.
+    fn fstat(&mut self, fd: u32) -> Result<FileStat, Errno>;
+
+    /// Deletes a file.
+    fn unlink(&mut self, _filename: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    /// Returns the target of a symbolic link.
+    fn readlink(&mut self, _filename: &str) -> Result<Vec<u8>, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Selects the filesystem of the process `pid` for subsequent operations.
+    ///
+    /// A `pid` of 0 selects the filesystem of the stub itself. By default, all
+    /// processes share the same filesystem.
+    fn setfs(&mut self, _pid: u32) -> Result<(), Errno> {
+        Ok(())
+    }
+}
+
+/// A `HostIo` implementation that exposes a directory on the host as the
+/// target's root directory.
+///
+/// Paths are resolved relative to the root directory. Paths escaping the root
+/// (eg. through `..` or symbolic links) are rejected.
+#[derive(Debug)]
+pub struct SandboxedHostIo {
+    root: PathBuf,
+    files: HashMap<u32, File>,
+    next_fd: u32,
+    writable: bool,
+}
+
+impl SandboxedHostIo {
+    /// Creates a read-only sandbox rooted at `root`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        Ok(Self {
+            root: root.into().canonicalize()?,
+            files: HashMap::new(),
+            // don't hand out stdin/stdout/stderr
+            next_fd: 3,
+            writable: false,
+        })
+    }
+
+    /// Allows the debugger to create, modify and delete files in the sandbox.
+    pub fn writable(mut self, writable: bool) -> Self {
+        self.writable = writable;
+        self
+    }
+
+    /// Maps a target path to the corresponding host path inside the sandbox.
+    fn resolve(&self, filename: &str) -> Result<PathBuf, Errno> {
+        let mut path = self.root.clone();
+        for component in Path::new(filename).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => return Err(Errno::EACCES),
+            }
+        }
+
+        // resolve symlinks of the existing part of the path and make sure
+        // they don't lead out of the sandbox
+        let (existing, rest) = match path.canonicalize() {
+            Ok(canonical) => (canonical, None),
+            Err(_) => match (path.parent(), path.file_name()) {
+                // the leaf exists but can't be resolved, ie. it is a dangling
+                // symlink, whose target might be outside of the sandbox
+                _ if fs::symlink_metadata(&path).is_ok() => return Err(Errno::EACCES),
+                (Some(parent), Some(name)) => (parent.canonicalize()?, Some(name.to_owned())),
+                _ => return Err(Errno::ENOENT),
+            },
+        };
+        if !existing.starts_with(&self.root) {
+            return Err(Errno::EACCES);
+        }
+
+        Ok(match rest {
+            Some(name) => existing.join(name),
+            None => existing,
+        })
+    }
+
+    fn file(&mut self, fd: u32) -> Result<&mut File, Errno> {
+        self.files.get_mut(&fd).ok_or(Errno::EBADF)
+    }
+}
+
+impl HostIo for SandboxedHostIo {
+    fn open(&mut self, filename: &str, flags: OpenFlags, _mode: u32) -> Result<u32, Errno> {
+        if flags.write() && !self.writable {
+            return Err(Errno::EROFS);
+        }
+
+        let path = self.resolve(filename)?;
+        let file = flags.to_open_options().open(path)?;
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    fn close(&mut self, fd: u32) -> Result<(), Errno> {
+        self.files.remove(&fd).map(|_| ()).ok_or(Errno::EBADF)
+    }
+
+    fn pread(&mut self, fd: u32, buf: &mut [u8], offset: u64) -> Result<usize, Errno> {
+        let file = self.file(fd)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file.read(buf)?)
+    }
+
+    fn pwrite(&mut self, fd: u32, data: &[u8], offset: u64) -> Result<usize, Errno> {
+        let file = self.file(fd)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file.write(data)?)
+    }
+
+    fn fstat(&mut self, fd: u32) -> Result<FileStat, Errno> {
+        let meta = self.file(fd)?.metadata()?;
+        Ok(FileStat::from_metadata(&meta))
+    }
+
+    fn unlink(&mut self, filename: &str) -> Result<(), Errno> {
+        if !self.writable {
+            return Err(Errno::EROFS);
+        }
+
+        let path = self.resolve(filename)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn readlink(&mut self, filename: &str) -> Result<Vec<u8>, Errno> {
+        // resolve the parent only, the link itself must not be followed
+        let path = Path::new(filename);
+        let name = path.file_name().ok_or(Errno::EINVAL)?;
+        let parent = self.resolve(path.parent().and_then(Path::to_str).unwrap_or("/"))?;
+        let target = fs::read_link(parent.join(name))?;
+        Ok(target.to_string_lossy().into_owned().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A temporary directory containing a `root` directory for the sandbox,
+    /// removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+            let dir = env::temp_dir().join(format!("gdbstub-hostio-{}-{}", process::id(), n));
+            fs::create_dir_all(dir.join("root/dir")).unwrap();
+            TempDir(dir.canonicalize().unwrap())
+        }
+
+        fn root(&self) -> PathBuf {
+            self.0.join("root")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn resolve_inside_root() {
+        let tmp = TempDir::new();
+        let sandbox = SandboxedHostIo::new(tmp.root()).unwrap();
+        assert_eq!(sandbox.resolve("/dir/new.txt"), Ok(tmp.root().join("dir/new.txt")));
+        assert_eq!(sandbox.resolve("dir/./new.txt"), Ok(tmp.root().join("dir/new.txt")));
+        assert_eq!(sandbox.resolve("/"), Ok(tmp.root()));
+    }
+
+    #[test]
+    fn resolve_rejects_parent_dirs() {
+        let tmp = TempDir::new();
+        let sandbox = SandboxedHostIo::new(tmp.root()).unwrap();
+        assert_eq!(sandbox.resolve("../outside"), Err(Errno::EACCES));
+        assert_eq!(sandbox.resolve("dir/../../outside"), Err(Errno::EACCES));
+        assert_eq!(sandbox.resolve("missing/file"), Err(Errno::ENOENT));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new();
+        fs::write(tmp.0.join("secret"), b"secret").unwrap();
+        symlink(tmp.root().join("dir"), tmp.root().join("inside")).unwrap();
+        symlink(tmp.0.join("secret"), tmp.root().join("escape")).unwrap();
+        symlink(&tmp.0, tmp.root().join("escape_dir")).unwrap();
+        symlink(tmp.0.join("created"), tmp.root().join("dangling")).unwrap();
+
+        let sandbox = SandboxedHostIo::new(tmp.root()).unwrap();
+        assert_eq!(sandbox.resolve("inside/file"), Ok(tmp.root().join("dir/file")));
+        assert_eq!(sandbox.resolve("escape"), Err(Errno::EACCES));
+        assert_eq!(sandbox.resolve("escape_dir/secret"), Err(Errno::EACCES));
+        assert_eq!(sandbox.resolve("escape_dir/new"), Err(Errno::EACCES));
+        assert_eq!(sandbox.resolve("dangling"), Err(Errno::EACCES));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_through_dangling_symlink() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new();
+        symlink(tmp.0.join("created"), tmp.root().join("dangling")).unwrap();
+
+        let mut sandbox = SandboxedHostIo::new(tmp.root()).unwrap().writable(true);
+        let flags = OpenFlags(OpenFlags::WRONLY | OpenFlags::CREAT);
+        assert_eq!(sandbox.open("dangling", flags, 0o644), Err(Errno::EACCES));
+        assert!(!tmp.0.join("created").exists());
+        assert!(sandbox.open("dir/created", flags, 0o644).is_ok());
+        assert!(tmp.root().join("dir/created").exists());
+    }
+}
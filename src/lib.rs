@@ -8,25 +8,46 @@
 //! Does not yet handle retransmission. Use a reliable communication channel
 //! instead.
 
+#![allow(clippy::result_unit_err)]
+
 #[macro_use] extern crate log;
 extern crate byteorder;
 
+mod agent;
 mod comm;
+mod console;
 mod error;
+pub mod fileio;
+pub mod hostio;
+pub mod library;
+pub mod memory;
 mod proto;
+pub mod record;
+pub mod rtos;
+mod signal;
 pub mod targets;
+mod tid;
+mod trace;
 mod utils;
 
+use agent::Agent;
 use comm::*;
 pub use comm::Comm;
+pub use console::Console;
 pub use error::Error;
+pub use signal::Signal;
+pub use tid::Tid;
 
-use proto::{Command, ParseError, ThreadAction, ThreadId};
-use targets::{Register, TargetDesc};
-
-use byteorder::LittleEndian;
+use fileio::{FileIoReply, FileIoRequest};
+use hostio::{FileStat, HostIo};
+use library::{libraries_svr4_xml, libraries_xml, Library, SectionOffsets, Svr4LibraryList};
+use memory::{memory_map_xml, FlashBatch, MemoryKind, MemoryRegion};
+use proto::{Command, HostIoCommand, ParseError, ThreadAction, ThreadId, VContKind};
+use targets::{target_xml, Register, TargetDesc};
+use trace::{FrameSelector, Trace, Tracepoint, TraceVariable};
+use utils::{crc32_update, xml_escape};
 
-use std::{mem, str, thread};
+use std::{cmp, mem, str, thread};
 
 /// This trait provides an interface between GDB and the target program and must
 /// be implemented by the user.
@@ -49,6 +70,19 @@ pub trait StubCalls {
     /// Returns an error if `addr` does not point to valid (mapped) memory.
     fn read_mem(&mut self, addr: u64) -> Result<u8, ()>;
 
+    /// Reads `buf.len()` bytes from the target system's memory, starting at
+    /// `addr`.
+    ///
+    /// Returns an error if any of the bytes can't be read. This is used for
+    /// searching memory, and by default calls `read_mem` for every byte.
+    /// Targets with faster access to larger blocks of memory can override it.
+    fn read_mem_range(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), ()> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.read_mem(addr.checked_add(i as u64).ok_or(())?)?;
+        }
+        Ok(())
+    }
+
     /// Writes a byte to the target system's memory.
     ///
     /// This is used to manually modify memory and to insert breakpoints.
@@ -59,37 +93,327 @@ pub trait StubCalls {
     /// writeable).
     fn write_mem(&mut self, addr: u64, byte: u8) -> Result<(), ()>;
 
+    /// Inserts a software breakpoint at `addr`.
+    ///
+    /// `kind` is architecture-specific, usually the size of the breakpoint
+    /// instruction to insert. It is 0 for the breakpoints the stub inserts
+    /// at tracepoints, in which case the default breakpoint kind should be
+    /// used.
+    ///
+    /// Returns `None` if the target doesn't manage breakpoints itself, in
+    /// which case GDB inserts them by writing breakpoint instructions to
+    /// memory, and tracepoints are unavailable. This doesn't need to be
+    /// implemented.
+    fn insert_breakpoint(&mut self, _addr: u64, _kind: u64) -> Option<Result<(), ()>> {
+        None
+    }
+
+    /// Removes a software breakpoint inserted with `insert_breakpoint`.
+    fn remove_breakpoint(&mut self, _addr: u64, _kind: u64) -> Option<Result<(), ()>> {
+        None
+    }
+
     /// Continue running the target program until a signal is received or a
     /// breakpoint is hit.
-    fn cont(&mut self);
+    ///
+    /// Output of the target program (eg. from an emulated UART) can be written
+    /// to `console` while running and will be displayed by GDB.
+    ///
+    /// Returns why the target stopped.
+    fn cont(&mut self, console: &mut Console) -> StopReason;
 
     /// Execute the next instruction of the target program and return control to
     /// the debugger.
-    fn step(&mut self);
+    ///
+    /// Output of the target program can be written to `console`.
+    ///
+    /// Returns why the target stopped.
+    fn step(&mut self, console: &mut Console) -> StopReason;
+
+    /// Returns whether the last `step` stopped the target program on its own
+    /// (eg. because it executed a breakpoint instruction), rather than just
+    /// finishing the instruction.
+    ///
+    /// `step` returns `StopReason::Trap` in both cases. This is used by
+    /// `record::Recorder`, which continues by stepping the target, to decide
+    /// when to stop. By default, stepping never stops the program.
+    fn step_stopped(&mut self) -> bool {
+        false
+    }
+
+    /// Returns the memory written by the last `step`, as the addresses of the
+    /// written bytes and the values they held before the step.
+    ///
+    /// This is used by `record::Recorder` to undo steps. If this returns
+    /// `None` (the default), the recorder finds the changes by comparing the
+    /// RAM regions of the memory map before and after each step, which is
+    /// slow for large memories.
+    fn step_writes(&mut self) -> Option<Vec<(u64, u8)>> {
+        None
+    }
+
+    /// Returns whether the target can execute backwards.
+    ///
+    /// If this returns `true`, `reverse_cont` and `reverse_step` must be
+    /// implemented. This is usually only possible for record/replay
+    /// emulators that can restore earlier states of the system.
+    fn can_reverse(&mut self) -> bool {
+        false
+    }
+
+    /// Run the target program backwards until a breakpoint is hit or the
+    /// beginning of the execution history is reached
+    /// (`StopReason::HistoryBegin`).
+    ///
+    /// This is used by GDB's `reverse-continue` command and only needs to be
+    /// implemented if `can_reverse` returns `true`.
+    fn reverse_cont(&mut self, _console: &mut Console) -> StopReason {
+        StopReason::HistoryBegin
+    }
+
+    /// Undo the last executed instruction of the target program.
+    ///
+    /// This is used by GDB's `reverse-stepi` command and only needs to be
+    /// implemented if `can_reverse` returns `true`.
+    fn reverse_step(&mut self, _console: &mut Console) -> StopReason {
+        StopReason::HistoryBegin
+    }
+
+    /// Receives the result of a File-I/O request made by returning
+    /// `StopReason::FileIo` from `cont` or `step`.
+    ///
+    /// The target is resumed afterwards, unless the user interrupted it (see
+    /// `FileIoReply::ctrl_c`). This doesn't need to be implemented if the
+    /// target doesn't make File-I/O requests.
+    fn file_io_reply(&mut self, _reply: FileIoReply) {}
+
+    /// Delivers a signal to the target program before it is resumed.
+    ///
+    /// GDB does this when the user continues after the target stopped with a
+    /// signal (see `StopReason::Signal`), or with GDB's `signal` command. The
+    /// target should handle it the way the program would receive it, eg. by
+    /// raising the corresponding exception or interrupt.
+    ///
+    /// This doesn't need to be implemented. By default, signals are discarded.
+    fn deliver_signal(&mut self, _signal: Signal) {}
 
     /// Kill the target program / system.
     ///
     /// This doesn't need to be implemented. GDB sends this when closing the
     /// connection.
     fn kill(&mut self) {}
-}
 
-trait CommExt: Comm {
-    fn write_response<F>(&mut self, f: F) -> Result<(), Self::Error>
-        where F: FnOnce(&mut ChecksumComm<Self>) -> Result<(), Self::Error>;
-}
+    /// Restart the target program / system from the beginning.
+    ///
+    /// This is used by GDB's `run` command in extended mode. It doesn't need
+    /// to be implemented.
+    fn restart(&mut self) -> Result<(), ()> {
+        Err(())
+    }
 
-impl<T: Comm> CommExt for T {
-    fn write_response<F>(&mut self, f: F) -> Result<(), Self::Error>
-        where F: FnOnce(&mut ChecksumComm<Self>) -> Result<(), Self::Error> {
-        self.write(b'$')?;
-        let checksum = {
-            let mut check = ChecksumComm::new(self);
-            f(&mut check)?;
-            check.into_checksum()
-        };
-        self.write(b'#')?;
-        self.write_hex(checksum)
+    /// Start a new target program.
+    ///
+    /// `filename` is the program to run, or `None` if GDB didn't specify one
+    /// (in which case the previously run program should be used). `args` are
+    /// the arguments passed to the program.
+    ///
+    /// This is used by GDB's `run` command in extended mode. The default
+    /// implementation restarts the current program if no other program is
+    /// requested, and fails otherwise.
+    fn run(&mut self, filename: Option<&str>, _args: &[&str]) -> Result<(), ()> {
+        match filename {
+            None => self.restart(),
+            Some(_) => Err(()),
+        }
+    }
+
+    /// Attach to an existing process with the given process ID.
+    ///
+    /// This is used by GDB's `attach` command in extended mode. It doesn't
+    /// need to be implemented.
+    fn attach(&mut self, _pid: u32) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// Detach the debugger from the target program / system.
+    ///
+    /// The target should resume running without the debugger (eg. by
+    /// removing breakpoints it inserted on its own). This doesn't need to be
+    /// implemented. GDB sends this when the user runs `detach`.
+    fn detach(&mut self) {}
+
+    /// Kill a single process of the target.
+    ///
+    /// This is only used when the target has multiple processes (see
+    /// `threads`). By default, it kills the whole target using `kill`.
+    fn kill_process(&mut self, _pid: u32) {
+        self.kill()
+    }
+
+    /// Detach the debugger from a single process of the target.
+    ///
+    /// This is only used when the target has multiple processes (see
+    /// `threads`). By default, it detaches from the whole target using
+    /// `detach`.
+    fn detach_process(&mut self, _pid: u32) {
+        self.detach()
+    }
+
+    /// Returns the threads of all processes (inferiors) of the target.
+    ///
+    /// This doesn't need to be implemented. By default, the target consists
+    /// of a single process with a single thread (`Tid::default()`).
+    fn threads(&mut self) -> Vec<Tid> {
+        vec![Tid::default()]
+    }
+
+    /// Selects the thread whose registers and memory are accessed by
+    /// subsequent calls, and which is stepped by `step`.
+    ///
+    /// This doesn't need to be implemented for targets with a single thread.
+    fn set_thread(&mut self, _thread: Tid) {}
+
+    /// Returns the name of a thread, shown by GDB's `info threads`.
+    ///
+    /// This doesn't need to be implemented.
+    fn thread_name(&mut self, _thread: Tid) -> Option<String> {
+        None
+    }
+
+    /// Returns the number of the CPU core a thread runs on.
+    ///
+    /// This doesn't need to be implemented.
+    fn thread_core(&mut self, _thread: Tid) -> Option<u32> {
+        None
+    }
+
+    /// Returns free-form information about the state of a thread (eg.
+    /// `Blocked on semaphore`), shown by GDB's `info threads`.
+    ///
+    /// This doesn't need to be implemented.
+    fn thread_extra_info(&mut self, _thread: Tid) -> Option<String> {
+        None
+    }
+
+    /// Returns the currently selected thread.
+    ///
+    /// After `cont` or `step` return, this should be the thread that caused
+    /// the target to stop. This doesn't need to be implemented for targets
+    /// with a single thread.
+    fn current_thread(&mut self) -> Tid {
+        Tid::default()
+    }
+
+    /// Handles a `monitor` command entered in GDB.
+    ///
+    /// `cmd` is the command text following `monitor`. Output for the user can
+    /// be written to `console` while the command executes.
+    ///
+    /// This doesn't need to be implemented. By default, all commands are
+    /// rejected with an error.
+    fn monitor(&mut self, _cmd: &str, _console: &mut Console) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// Returns the path of the executable file of a process.
+    ///
+    /// `pid` is the process to query, or `None` for the current process. This
+    /// doesn't need to be implemented. If it returns `Some`, GDB loads the
+    /// symbols of the executable automatically.
+    fn exec_file(&mut self, _pid: Option<u32>) -> Option<String> {
+        None
+    }
+
+    /// Returns the offsets by which the main executable has been relocated
+    /// when it was loaded.
+    ///
+    /// This doesn't need to be implemented. If it returns `Some`, GDB
+    /// relocates the symbols of the executable accordingly.
+    fn section_offsets(&mut self) -> Option<SectionOffsets> {
+        None
+    }
+
+    /// Returns the libraries (or other modules) loaded by the target program.
+    ///
+    /// This doesn't need to be implemented. If it returns `Some`, GDB loads
+    /// the symbols of the libraries at the reported addresses. When the list
+    /// changes while the target is running, `cont` should return
+    /// `StopReason::LibrariesChanged` so that GDB reloads it.
+    fn libraries(&mut self) -> Option<Vec<Library>> {
+        None
+    }
+
+    /// Returns the libraries loaded by the target program in the SVR4 format
+    /// used by ELF-based systems.
+    ///
+    /// This is preferred by GDB over `libraries` when debugging programs that
+    /// use a dynamic linker. It doesn't need to be implemented.
+    fn libraries_svr4(&mut self) -> Option<Svr4LibraryList> {
+        None
+    }
+
+    /// Returns the names of symbols whose addresses the target wants to know.
+    ///
+    /// This is called whenever GDB has loaded new symbols. GDB then looks up
+    /// each symbol and passes the result to `symbol_resolved`. This doesn't
+    /// need to be implemented.
+    fn symbols_needed(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Receives the address of a symbol requested by `symbols_needed`.
+    ///
+    /// `addr` is `None` if GDB couldn't find the symbol.
+    fn symbol_resolved(&mut self, _name: &str, _addr: Option<u64>) {}
+
+    /// Returns the handler for file operations requested by the debugger.
+    ///
+    /// This doesn't need to be implemented. If it returns `Some`, GDB can
+    /// access files on the target side, eg. to load the executable and its
+    /// shared libraries automatically. `hostio::SandboxedHostIo` provides an
+    /// implementation exposing a host directory.
+    fn host_io(&mut self) -> Option<&mut dyn HostIo> {
+        None
+    }
+
+    /// Returns the memory map of the target system.
+    ///
+    /// This doesn't need to be implemented. If it returns `Some`, the memory
+    /// map is reported to GDB, which uses it to decide how to write to the
+    /// listed regions (eg. flash memory). Memory outside of the listed regions
+    /// is considered inaccessible by GDB.
+    fn memory_map(&mut self) -> Option<Vec<MemoryRegion>> {
+        None
+    }
+
+    /// Erases a range of flash memory.
+    ///
+    /// This is called when GDB finishes a flash programming sequence, before
+    /// the new data is written using `flash_write`. The range is aligned to
+    /// the block size reported in the memory map.
+    ///
+    /// This doesn't need to be implemented if the memory map doesn't contain
+    /// flash memory.
+    fn flash_erase(&mut self, _start: u64, _length: u64) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// Writes data to (previously erased) flash memory.
+    ///
+    /// Contiguous writes requested by GDB are merged into a single call.
+    ///
+    /// This doesn't need to be implemented if the memory map doesn't contain
+    /// flash memory.
+    fn flash_write(&mut self, _start: u64, _data: &[u8]) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// Called after all erase and write operations of a flash programming
+    /// sequence have been performed.
+    ///
+    /// This doesn't need to be implemented.
+    fn flash_done(&mut self) -> Result<(), ()> {
+        Ok(())
     }
 }
 
@@ -137,6 +461,61 @@ impl<'a, C: Comm> Drop for ResponseWriter<'a, C> {
     }
 }
 
+/// The reason why the target stopped running and returned control to the
+/// debugger.
+#[derive(Debug, Copy, Clone)]
+pub enum StopReason {
+    /// The target hit a breakpoint or finished a single step.
+    Trap,
+    /// The target program received a signal (eg. an emulated exception).
+    ///
+    /// Signals passed with GDB's `handle <signal> nopass` are reported to the
+    /// user. All others are delivered to the target when it is resumed. If
+    /// GDB was told not to stop for a signal, it is delivered right away
+    /// without involving the debugger.
+    Signal(Signal),
+    /// Reverse execution reached the beginning of the execution history.
+    HistoryBegin,
+    /// Replaying the execution history reached its end, so the target can't
+    /// run forward without recording new history.
+    HistoryEnd,
+    /// The list of loaded libraries changed.
+    ///
+    /// GDB reloads the library list and resumes the target automatically.
+    LibrariesChanged,
+    /// The target program requests GDB to perform a system call on the host.
+    ///
+    /// The target is resumed after `StubCalls::file_io_reply` was called with
+    /// the result.
+    FileIo(FileIoRequest),
+}
+
+/// How the target is resumed.
+#[derive(Debug, Copy, Clone)]
+enum Resume {
+    Continue,
+    Step,
+    ReverseContinue,
+    ReverseStep,
+    /// Step while the PC is in `start..end`.
+    StepRange { start: u64, end: u64 },
+}
+
+/// The reason why `GdbStub::poll` stopped serving the debugger.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Disconnect {
+    /// The debugger detached and the target should keep running.
+    Detached,
+    /// The debugger killed the target.
+    ///
+    /// This isn't returned in extended mode, where the debugger can start a
+    /// new program after killing the old one.
+    Killed,
+    /// The connection to the debugger was closed or broke down between
+    /// packets.
+    ConnectionLost,
+}
+
 /// A GDB target connected via the remote debugging protocol.
 pub struct GdbStub<C: Comm, T: StubCalls> {
     comm: C,
@@ -148,6 +527,44 @@ pub struct GdbStub<C: Comm, T: StubCalls> {
     thread_cont_step: ThreadId,
     /// Active thread for other operations.
     thread_other: ThreadId,
+    /// Pending flash operations.
+    flash: FlashBatch,
+    /// Whether the debugger enabled extended mode.
+    extended: bool,
+    /// Whether the target program is alive (ie. hasn't been killed or detached
+    /// from in extended mode).
+    alive: bool,
+    /// Whether the debugger uses the multiprocess extension.
+    multiprocess: bool,
+    /// Processes we attached to using `vAttach`.
+    attached: Vec<u32>,
+    /// How to resume the target after a pending File-I/O request completes.
+    file_io_resume: Option<Resume>,
+    /// Symbols that still need to be looked up by the debugger (in reverse
+    /// order).
+    symbol_queue: Vec<String>,
+    /// Signals that are delivered to the target without reporting them to the
+    /// debugger (`QPassSignals`).
+    pass_signals: Vec<Signal>,
+    /// Signals the debugger may deliver to the target (`QProgramSignals`), or
+    /// `None` to allow all of them.
+    program_signals: Option<Vec<Signal>>,
+    /// The software breakpoints inserted by the target.
+    breakpoints: Vec<Breakpoint>,
+    /// The tracepoints and the trace buffer.
+    trace: Trace,
+}
+
+/// A software breakpoint inserted by the target.
+struct Breakpoint {
+    addr: u64,
+    kind: u64,
+    /// Agent expressions, at least one of which must be true for the
+    /// breakpoint to stop the target. Empty for unconditional breakpoints.
+    conditions: Vec<Vec<u8>>,
+    /// Agent expressions run when the breakpoint is hit (eg. for `dprintf`),
+    /// after which the target is resumed without reporting the stop.
+    commands: Vec<Vec<u8>>,
 }
 
 impl<C: Comm, T: StubCalls> GdbStub<C, T> {
@@ -158,39 +575,60 @@ impl<C: Comm, T: StubCalls> GdbStub<C, T> {
             target,
             buf: Vec::new(),
             next: 0,
-            thread_cont_step: ThreadId::All,
-            thread_other: ThreadId::Any,
+            thread_cont_step: ThreadId::ALL,
+            thread_other: ThreadId::ANY,
+            flash: FlashBatch::default(),
+            extended: false,
+            alive: true,
+            multiprocess: false,
+            attached: Vec::new(),
+            file_io_resume: None,
+            symbol_queue: Vec::new(),
+            pass_signals: Vec::new(),
+            program_signals: None,
+            breakpoints: Vec::new(),
+            trace: Trace::new(),
         }
     }
 
     /// Starts polling for and replying to incoming commands.
     ///
-    /// This blocks until the debugger closes the connection.
+    /// This blocks until the debugger closes the connection, and returns why
+    /// it did so. Errors are only returned when the communication with the
+    /// debugger fails in the middle of an exchange.
     // FIXME: Rename? It practically does interactive debugging.
-    pub fn poll(mut self) -> Result<(), Error> {
+    pub fn poll(mut self) -> Result<Disconnect, Error> {
         loop {
-            self.next = self.read()?;
+            self.next = match self.read() {
+                Ok(b) => b,
+                Err(e) => {
+                    info!("lost connection to debugger: {}", e);
+                    return Ok(Disconnect::ConnectionLost);
+                }
+            };
             match self.next {
                 b'$' => {
                     self.read_packet()?;
                     self.write(b'+')?;  // ACK the transmission
 
-                    let mut buf = mem::replace(&mut self.buf, Vec::new());
-                    let result = || -> Result<(), Error> {
-                        match Command::parse(&mut buf) {
-                            Ok(cmd) => {
-                                trace!("{:?}", cmd);
-                                self.handle_cmd(cmd)
-                            }
-                            Err(ParseError::Unsupported) => self.write_response(|_| Ok(())),
-                            Err(ParseError::Malformed) => Err(Error::Malformed),
+                    let mut buf = mem::take(&mut self.buf);
+                    let result = match Command::parse(&mut buf) {
+                        Ok(cmd) => {
+                            trace!("{:?}", cmd);
+                            self.handle_cmd(cmd)
                         }
-                    }();
+                        Err(ParseError::Unsupported) => self.write_response(|_| Ok(())),
+                        Err(ParseError::Malformed) => Err(Error::Malformed),
+                    };
                     self.buf = buf;
                     match result {
                         Err(Error::Killed) => {
                             info!("debugger killed connection");
-                            return Ok(());
+                            return Ok(Disconnect::Killed);
+                        },
+                        Err(Error::Detached) => {
+                            info!("debugger detached");
+                            return Ok(Disconnect::Detached);
                         },
                         res => res?,    // Ok => continue
                     }
@@ -207,60 +645,322 @@ impl<C: Comm, T: StubCalls> GdbStub<C, T> {
     /// The command packet must already be acknowledged.
     fn handle_cmd(&mut self, cmd: Command) -> Result<(), Error> {
         match cmd {
-            Command::GetHaltReason => self.write_response(|c| c.write_all(b"S00")),
+            Command::GetHaltReason => if self.alive {
+                self.write_stop_reply(Signal::NONE, "")
+            } else {
+                // terminated by SIGKILL
+                self.write_response(|c| {
+                    c.write(b'X')?;
+                    c.write_hex(Signal::SIGKILL.0)
+                })
+            },
             Command::ReadRegisters => {
+                if let Some(raw) = self.trace.frame_registers() {
+                    let mut resp = ResponseWriter::new(&mut self.comm)?;
+                    resp.write_all_hex(raw).map_err(Error::comm)?;
+                    return resp.finish();
+                }
+
                 let regs = self.target.read_registers();
-                self.write_response(|comm| regs.encode::<_, LittleEndian>(comm))
+                self.write_response(|comm| regs.encode::<_, <T::Target as TargetDesc>::Endianness>(comm))
             },
             Command::WriteRegisters { raw } => {
                 let mut r = raw;
-                let regs = <T::Target as TargetDesc>::Registers::decode::<_, <T::Target as TargetDesc>::Endianness>(&mut r)
-                    .map_err(Error::comm)?;
-                self.target.write_registers(regs);
+                // decode into the current registers, since their layout might
+                // only be known at runtime
+                let mut regs = self.target.read_registers();
+                let decoded = regs.decode_in_place::<_, <T::Target as TargetDesc>::Endianness>(&mut r);
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match decoded {
+                    Ok(()) => {
+                        self.target.write_registers(regs);
+                        resp.write_all(b"OK").map_err(Error::comm)?;
+                    }
+                    Err(e) => {
+                        // eg. fewer bytes than the current register set needs
+                        debug!("invalid register data: {}", e);
+                        resp.write_all(b"E01").map_err(Error::comm)?;
+                    }
+                }
+                resp.finish()
+            }
+            Command::Kill => {
+                self.target.kill();
+                if self.extended {
+                    // keep serving the debugger, it might start a new program
+                    self.alive = false;
+                    Ok(())
+                } else {
+                    Err(Error::Killed)
+                }
+            }
+            Command::KillProcess { pid } => {
+                if self.multiprocess {
+                    debug!("killing process {}", pid);
+                    self.target.kill_process(pid);
+                    self.alive = self.target.threads().iter().any(|thread| thread.pid != pid);
+                } else {
+                    // without the multiprocess extension, GDB sends a
+                    // placeholder pid and sees the target as a single process
+                    let mut pids: Vec<_> = self.target.threads().iter().map(|thread| thread.pid).collect();
+                    pids.sort_unstable();
+                    pids.dedup();
+                    for pid in pids {
+                        debug!("killing process {}", pid);
+                        self.target.kill_process(pid);
+                    }
+                    self.alive = false;
+                }
 
                 let mut resp = ResponseWriter::new(&mut self.comm)?;
                 resp.write_all(b"OK").map_err(Error::comm)?;
                 resp.finish()?;
                 Ok(())
             }
-            Command::Kill => {
-                self.target.kill();
-                Err(Error::Killed)
+            Command::ExtendedMode => {
+                self.extended = true;
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()?;
+                Ok(())
             }
-            Command::SetThread { action, thread } => {
-                match action {
-                    ThreadAction::ContStep => self.thread_cont_step = thread,
-                    ThreadAction::Other => self.thread_other = thread,
+            Command::Restart => {
+                // no response is sent for `R`
+                match self.target.restart() {
+                    Ok(()) => self.alive = true,
+                    Err(()) => warn!("target does not support restarting"),
+                }
+                Ok(())
+            }
+            Command::Run { filename, args } => {
+                let result = self.target.run(filename, &args);
+                self.write_start_response(result)
+            }
+            Command::Attach { pid } => {
+                let result = self.target.attach(pid);
+                if result.is_ok() {
+                    self.attached.push(pid);
                 }
+                self.write_start_response(result)
+            }
+            Command::Detach { pid } => {
+                let remaining = match pid {
+                    Some(pid) => {
+                        debug!("detaching from process {}", pid);
+                        self.target.detach_process(pid);
+                        self.attached.retain(|attached| *attached != pid);
+                        self.target.threads().iter().any(|thread| thread.pid != pid)
+                    }
+                    None => {
+                        self.target.detach();
+                        false
+                    }
+                };
 
                 let mut resp = ResponseWriter::new(&mut self.comm)?;
                 resp.write_all(b"OK").map_err(Error::comm)?;
                 resp.finish()?;
+                if remaining {
+                    // keep debugging the other processes
+                    Ok(())
+                } else if self.extended {
+                    // keep serving the debugger, it might run or attach to
+                    // another program
+                    self.alive = false;
+                    Ok(())
+                } else {
+                    Err(Error::Detached)
+                }
+            }
+            Command::SetThread { action, thread } => {
+                let found = self.find_thread(thread);
+                let valid = match action {
+                    ThreadAction::ContStep => {
+                        self.thread_cont_step = thread;
+                        found.is_some() || !thread.is_single()
+                    }
+                    ThreadAction::Other => {
+                        self.thread_other = thread;
+                        if let Some(tid) = found {
+                            self.target.set_thread(tid);
+                        }
+                        found.is_some()
+                    }
+                };
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                if valid {
+                    resp.write_all(b"OK").map_err(Error::comm)?;
+                } else {
+                    resp.write_all(b"E01").map_err(Error::comm)?;
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::ThreadAlive { thread } => {
+                let alive = self.find_thread(thread).is_some();
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                if alive {
+                    resp.write_all(b"OK").map_err(Error::comm)?;
+                } else {
+                    resp.write_all(b"E01").map_err(Error::comm)?;
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::CurrentThread => {
+                let current = self.target.current_thread();
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"QC").map_err(Error::comm)?;
+                current.write(&mut resp, self.multiprocess).map_err(Error::comm)?;
+                resp.finish()?;
                 Ok(())
             }
-            Command::Continue => {
-                self.target.cont();
+            Command::ThreadInfoFirst => {
+                let threads = self.target.threads();
 
+                // all threads are sent in the first response
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write(b'm').map_err(Error::comm)?;
+                for (i, thread) in threads.iter().enumerate() {
+                    if i != 0 {
+                        resp.write(b',').map_err(Error::comm)?;
+                    }
+                    thread.write(&mut resp, self.multiprocess).map_err(Error::comm)?;
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::ThreadInfoNext => {
                 let mut resp = ResponseWriter::new(&mut self.comm)?;
-                resp.write_all(b"S05").map_err(Error::comm)?; // 05 is apparently the trap signal
+                resp.write(b'l').map_err(Error::comm)?;
                 resp.finish()?;
                 Ok(())
             }
-            Command::Step => {
-                self.target.step();
+            Command::ThreadExtraInfo { thread } => {
+                let info = match self.find_thread(thread) {
+                    Some(tid) => self.target.thread_extra_info(tid),
+                    None => None,
+                };
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all_hex(info.unwrap_or_default().as_bytes()).map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::Attached { pid } => {
+                let pid = pid.unwrap_or_else(|| self.target.current_thread().pid);
+                let attached = self.attached.contains(&pid);
 
                 let mut resp = ResponseWriter::new(&mut self.comm)?;
-                resp.write_all(b"S05").map_err(Error::comm)?; // 05 is apparently the trap signal
+                resp.write(if attached { b'1' } else { b'0' }).map_err(Error::comm)?;
                 resp.finish()?;
                 Ok(())
             }
+            Command::Continue { signal, addr } => {
+                self.select_cont_step_thread();
+                self.handle_resume(Resume::Continue, signal, addr)
+            }
+            Command::Step { signal, addr } => {
+                self.select_cont_step_thread();
+                self.handle_resume(Resume::Step, signal, addr)
+            }
+            Command::VContQuery => {
+                // range stepping needs to know the PC
+                let regs = self.target.read_registers();
+                let actions: &[u8] = if T::Target::pc(&regs).is_some() {
+                    b"vCont;c;C;s;S;r"
+                } else {
+                    b"vCont;c;C;s;S"
+                };
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(actions).map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::VCont { actions } => {
+                // in all-stop mode the whole target is resumed, so only the
+                // action for the thread that should run is relevant: the one
+                // given for a specific thread, or the default action
+                let action = actions.iter()
+                    .find(|action| action.thread.is_some_and(|thread| thread.is_single()))
+                    .or_else(|| actions.iter().find(|action| action.thread.is_none_or(|thread| !thread.is_single())));
+                let action = match action {
+                    Some(action) => action,
+                    None => return self.write_response(|_| Ok(())),
+                };
+
+                if let Some(thread) = action.thread.filter(|thread| thread.is_single()) {
+                    if let Some(tid) = self.find_thread(thread) {
+                        self.target.set_thread(tid);
+                    }
+                }
+
+                match action.kind {
+                    VContKind::Continue(signal) => self.handle_resume(Resume::Continue, signal, None),
+                    VContKind::Step(signal) => self.handle_resume(Resume::Step, signal, None),
+                    VContKind::StepRange { start, end } => self.resume(Resume::StepRange { start, end }),
+                }
+            }
+            Command::ReverseContinue => {
+                self.select_cont_step_thread();
+                self.resume(Resume::ReverseContinue)
+            }
+            Command::ReverseStep => {
+                self.select_cont_step_thread();
+                self.resume(Resume::ReverseStep)
+            }
+            Command::PassSignals { signals } => {
+                self.pass_signals = signals;
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::ProgramSignals { signals } => {
+                self.program_signals = Some(signals);
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::FileIoReply(reply) => {
+                let resume = match self.file_io_resume.take() {
+                    Some(resume) => resume,
+                    None => {
+                        warn!("received File-I/O reply without pending request");
+                        return Ok(());
+                    }
+                };
+
+                self.target.file_io_reply(reply);
+                if reply.ctrl_c {
+                    self.write_stop_reply(Signal::SIGINT, "")
+                } else {
+                    self.resume(resume)
+                }
+            }
             Command::ReadMem { start, len } => {
                 trace!("reading {} bytes starting at {:#010X}", len, start);
                 let mut resp = ResponseWriter::new(&mut self.comm)?;
 
                 for addr in start..start+len {
-                    match self.target.read_mem(addr) {
+                    let byte = if self.trace.frame_selected() {
+                        self.trace.read_mem(&mut self.target, addr)
+                    } else {
+                        self.target.read_mem(addr)
+                    };
+                    match byte {
                         Ok(byte) => resp.write_hex(byte).map_err(Error::comm)?,
+                        // an empty response would mean the memory is empty,
+                        // so fail if nothing could be read
+                        Err(_) if addr == start => {
+                            resp.write_all(b"E01").map_err(Error::comm)?;
+                            break;
+                        }
                         // cancel on errors and return truncated response
                         Err(_) => break,
                     }
@@ -292,7 +992,827 @@ impl<C: Comm, T: StubCalls> GdbStub<C, T> {
 
                 Ok(())
             }
+            Command::InsertBreakpoint { addr, kind, conditions, commands, persist } => {
+                if persist {
+                    // the stub stops serving when the debugger disconnects
+                    debug!("breakpoint commands at {:#x} won't persist after disconnecting", addr);
+                }
+
+                let result = if self.at_breakpoint(addr) || self.tracepoint_installed(addr) {
+                    // the breakpoint is already there, inserting it again
+                    // only updates its conditions and commands
+                    Some(Ok(()))
+                } else {
+                    self.target.insert_breakpoint(addr, kind)
+                };
+                if result == Some(Ok(())) {
+                    self.breakpoints.retain(|bp| bp.addr != addr);
+                    self.breakpoints.push(Breakpoint {
+                        addr,
+                        kind,
+                        conditions: conditions.iter().map(|cond| cond.to_vec()).collect(),
+                        commands: commands.iter().map(|cmd| cmd.to_vec()).collect(),
+                    });
+                }
+                self.write_breakpoint_response(result)
+            }
+            Command::RemoveBreakpoint { addr, kind } => {
+                let result = if self.tracepoint_installed(addr) {
+                    Some(Ok(()))
+                } else {
+                    self.target.remove_breakpoint(addr, kind)
+                };
+                self.breakpoints.retain(|bp| bp.addr != addr);
+                self.write_breakpoint_response(result)
+            }
+            Command::TraceInit => {
+                if self.trace.is_running() {
+                    self.trace.stop();
+                    self.uninstall_tracepoints();
+                }
+                self.trace.clear();
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::DefineTracepoint { number, addr, enabled, step_count, pass_count, condition } => {
+                if step_count != 0 {
+                    warn!("while-stepping actions of tracepoint {} won't be collected", number);
+                }
+
+                self.trace.tracepoints.retain(|tp| tp.number != number || tp.addr != addr);
+                self.trace.tracepoints.push(Tracepoint {
+                    number,
+                    addr,
+                    enabled,
+                    pass_count,
+                    condition: condition.map(|cond| cond.to_vec()),
+                    actions: Vec::new(),
+                    hits: 0,
+                });
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::TracepointAction { number, addr, stepping, action } => {
+                let tp = self.trace.tracepoints.iter_mut().find(|tp| tp.number == number && tp.addr == addr);
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match tp {
+                    Some(tp) => {
+                        if !stepping {
+                            tp.actions.push(action);
+                        }
+                        resp.write_all(b"OK").map_err(Error::comm)?;
+                    }
+                    None => resp.write_all(b"E01").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::DefineTraceVariable { number, initial } => {
+                self.trace.variables.retain(|var| var.number != number);
+                self.trace.variables.push(TraceVariable { number, initial, value: initial });
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::TraceReadOnly { regions } => {
+                self.trace.readonly = regions;
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::TraceStart => {
+                if self.trace.is_running() {
+                    self.uninstall_tracepoints();
+                }
+                let installed = self.install_tracepoints();
+                if installed.is_ok() {
+                    self.trace.start();
+                }
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match installed {
+                    Ok(()) => resp.write_all(b"OK").map_err(Error::comm)?,
+                    Err(()) => resp.write_all(b"E01").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::TraceStop => {
+                if self.trace.is_running() {
+                    self.trace.stop();
+                    self.uninstall_tracepoints();
+                }
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"OK").map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::TraceStatus => {
+                let status = self.trace.status();
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(status.as_bytes()).map_err(Error::comm)?;
+                resp.finish()
+            }
+            Command::SelectTraceFrame(selector) => {
+                let frame = self.trace.select(&selector);
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match (selector, frame) {
+                    (FrameSelector::None, _) => resp.write_all(b"OK").map_err(Error::comm)?,
+                    (_, Some((frame, tp))) => {
+                        resp.write_all(format!("F{:x}T{:x}", frame, tp).as_bytes()).map_err(Error::comm)?
+                    }
+                    (FrameSelector::Number(_), None) => resp.write_all(b"E01").map_err(Error::comm)?,
+                    (_, None) => resp.write_all(b"F-1").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::TraceVariableValue { number } => {
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match self.trace.variable_value(number) {
+                    Some(value) => resp.write_all(format!("V{:x}", value as u64).as_bytes()).map_err(Error::comm)?,
+                    None => resp.write_all(b"U").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::Crc { start, len } => {
+                let crc = self.crc_memory(start, len);
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match crc {
+                    Ok(crc) => resp.write_all(format!("C{:x}", crc).as_bytes()).map_err(Error::comm)?,
+                    Err(()) => resp.write_all(b"E01").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::SearchMemory { start, len, pattern } => {
+                let found = self.search_memory(start, len, pattern);
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match found {
+                    Ok(Some(addr)) => resp.write_all(format!("1,{:x}", addr).as_bytes()).map_err(Error::comm)?,
+                    Ok(None) => resp.write(b'0').map_err(Error::comm)?,
+                    Err(()) => resp.write_all(b"E01").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::HostIo(cmd) => self.handle_host_io(cmd),
+            Command::SectionOffsets => match self.target.section_offsets() {
+                Some(offsets) => {
+                    let mut resp = ResponseWriter::new(&mut self.comm)?;
+                    resp.write_all(offsets.to_string().as_bytes()).map_err(Error::comm)?;
+                    resp.finish()
+                }
+                None => self.write_response(|_| Ok(())),
+            },
+            Command::Symbol { value, name } => {
+                match name {
+                    Some(name) => self.target.symbol_resolved(name, value),
+                    None => {
+                        // GDB is ready to serve symbol lookups
+                        self.symbol_queue = self.target.symbols_needed();
+                        self.symbol_queue.reverse();
+                    }
+                }
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match self.symbol_queue.pop() {
+                    Some(next) => {
+                        resp.write_all(b"qSymbol:").map_err(Error::comm)?;
+                        resp.write_all_hex(next.as_bytes()).map_err(Error::comm)?;
+                    }
+                    None => resp.write_all(b"OK").map_err(Error::comm)?,
+                }
+                resp.finish()
+            }
+            Command::Monitor { cmd } => {
+                let result = {
+                    let mut console = Console::new(&mut self.comm);
+                    self.target.monitor(cmd, &mut console)
+                };
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match result {
+                    Ok(()) => resp.write_all(b"OK").map_err(Error::comm)?,
+                    Err(()) => resp.write_all(b"E01").map_err(Error::comm)?,
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::FlashErase { start, length } => {
+                let valid = self.is_flash(start, length);
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                if valid {
+                    self.flash.erase(start, length);
+                    resp.write_all(b"OK").map_err(Error::comm)?;
+                } else {
+                    resp.write_all(b"E.memtype").map_err(Error::comm)?;
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::FlashWrite { start, data } => {
+                let valid = self.is_flash(start, data.len() as u64);
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                if valid {
+                    self.flash.write(start, data);
+                    resp.write_all(b"OK").map_err(Error::comm)?;
+                } else {
+                    resp.write_all(b"E.memtype").map_err(Error::comm)?;
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::FlashDone => {
+                let batch = mem::take(&mut self.flash);
+                let result = batch.erases.iter()
+                    .try_for_each(|&(start, length)| self.target.flash_erase(start, length))
+                    .and_then(|()| batch.writes.iter().try_for_each(|(start, data)| self.target.flash_write(*start, data)))
+                    .and_then(|()| self.target.flash_done());
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                match result {
+                    Ok(()) => resp.write_all(b"OK").map_err(Error::comm)?,
+                    Err(()) => resp.write_all(b"E01").map_err(Error::comm)?,
+                }
+                resp.finish()?;
+                Ok(())
+            }
+            Command::QuerySupported { features } => {
+                self.multiprocess = features.contains(&"multiprocess+");
+
+                let mut supported = vec![
+                    "qXfer:threads:read+",
+                    "QPassSignals+",
+                    "QProgramSignals+",
+                    "ConditionalBreakpoints+",
+                    "ConditionalTracepoints+",
+                    "BreakpointCommands+",
+                ];
+                if target_xml::<T::Target>(&self.target.read_registers()).is_some() {
+                    supported.push("qXfer:features:read+");
+                }
+                if self.multiprocess {
+                    supported.push("multiprocess+");
+                }
+                if self.target.memory_map().is_some() {
+                    supported.push("qXfer:memory-map:read+");
+                }
+                if self.target.exec_file(None).is_some() {
+                    supported.push("qXfer:exec-file:read+");
+                }
+                if self.target.libraries().is_some() {
+                    supported.push("qXfer:libraries:read+");
+                }
+                if self.target.libraries_svr4().is_some() {
+                    supported.push("qXfer:libraries-svr4:read+");
+                }
+                if self.target.can_reverse() {
+                    supported.push("ReverseContinue+");
+                    supported.push("ReverseStep+");
+                }
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(supported.join(";").as_bytes()).map_err(Error::comm)?;
+                resp.finish()?;
+                Ok(())
+            }
+            Command::ReadXfer { object, annex, offset, len } => match self.xfer_object(object, annex) {
+                Ok(Some(data)) => self.write_xfer_chunk(&data, offset, len),
+                // unsupported object
+                Ok(None) => self.write_response(|_| Ok(())),
+                Err(()) => {
+                    debug!("invalid qXfer annex '{}' for object '{}'", annex, object);
+                    let mut resp = ResponseWriter::new(&mut self.comm)?;
+                    resp.write_all(b"E00").map_err(Error::comm)?;
+                    resp.finish()
+                }
+            },
+        }
+    }
+
+    /// Performs a host I/O operation and sends the `F` response.
+    fn handle_host_io(&mut self, cmd: HostIoCommand) -> Result<(), Error> {
+        /// Maximum number of bytes returned by a single `pread`.
+        const MAX_PREAD: u64 = 0x4000;
+
+        let host_io = match self.target.host_io() {
+            Some(host_io) => host_io,
+            None => return self.write_response(|_| Ok(())),
+        };
+
+        // the result, and binary data attached to it
+        let (result, data) = match cmd {
+            HostIoCommand::Open { filename, flags, mode } => {
+                (host_io.open(filename, flags, mode).map(u64::from), Vec::new())
+            }
+            HostIoCommand::Close { fd } => (host_io.close(fd).map(|()| 0), Vec::new()),
+            HostIoCommand::Pread { fd, count, offset } => {
+                let mut buf = vec![0; cmp::min(count, MAX_PREAD) as usize];
+                match host_io.pread(fd, &mut buf, offset) {
+                    Ok(len) => {
+                        buf.truncate(len);
+                        (Ok(len as u64), buf)
+                    }
+                    Err(errno) => (Err(errno), Vec::new()),
+                }
+            }
+            HostIoCommand::Pwrite { fd, offset, data } => {
+                (host_io.pwrite(fd, data, offset).map(|len| len as u64), Vec::new())
+            }
+            HostIoCommand::Fstat { fd } => match host_io.fstat(fd) {
+                Ok(stat) => (Ok(FileStat::SIZE as u64), stat.encode().to_vec()),
+                Err(errno) => (Err(errno), Vec::new()),
+            },
+            HostIoCommand::Unlink { filename } => (host_io.unlink(filename).map(|()| 0), Vec::new()),
+            HostIoCommand::Readlink { filename } => match host_io.readlink(filename) {
+                Ok(target) => (Ok(target.len() as u64), target),
+                Err(errno) => (Err(errno), Vec::new()),
+            },
+            HostIoCommand::Setfs { pid } => (host_io.setfs(pid).map(|()| 0), Vec::new()),
+        };
+
+        let mut resp = ResponseWriter::new(&mut self.comm)?;
+        match result {
+            Ok(value) => {
+                resp.write_all(format!("F{:x}", value).as_bytes()).map_err(Error::comm)?;
+                if !data.is_empty() {
+                    resp.write(b';').map_err(Error::comm)?;
+                    resp.write_all_escaped(&data).map_err(Error::comm)?;
+                }
+            }
+            Err(errno) => resp.write_all(format!("F-1,{:x}", errno.0).as_bytes()).map_err(Error::comm)?,
+        }
+        resp.finish()
+    }
+
+    /// Responds to a request for inserting or removing a breakpoint.
+    ///
+    /// An empty response makes the debugger fall back to memory writes if the
+    /// target doesn't manage breakpoints.
+    fn write_breakpoint_response(&mut self, result: Option<Result<(), ()>>) -> Result<(), Error> {
+        let mut resp = ResponseWriter::new(&mut self.comm)?;
+        match result {
+            Some(Ok(())) => resp.write_all(b"OK").map_err(Error::comm)?,
+            Some(Err(())) => resp.write_all(b"E01").map_err(Error::comm)?,
+            None => {}
         }
+        resp.finish()
+    }
+
+    /// Responds to a request for starting or attaching to a program.
+    ///
+    /// On success, the program is considered stopped at its first
+    /// instruction.
+    fn write_start_response(&mut self, result: Result<(), ()>) -> Result<(), Error> {
+        match result {
+            Ok(()) => {
+                self.alive = true;
+                self.write_stop_reply(Signal::SIGTRAP, "")
+            }
+            Err(()) => {
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"E01").map_err(Error::comm)?;
+                resp.finish()
+            }
+        }
+    }
+
+    /// Resumes the target and reports why it stopped.
+    fn resume(&mut self, resume: Resume) -> Result<(), Error> {
+        let mut step_over = false;
+        let reason = loop {
+            let stepped_over = mem::replace(&mut step_over, false);
+            let reason = if stepped_over {
+                self.step_over_breakpoint()
+            } else {
+                let mut console = Console::new(&mut self.comm);
+                match resume {
+                    Resume::Continue => self.target.cont(&mut console),
+                    Resume::Step => self.target.step(&mut console),
+                    Resume::ReverseContinue => self.target.reverse_cont(&mut console),
+                    Resume::ReverseStep => self.target.reverse_step(&mut console),
+                    Resume::StepRange { start, end } => self.step_range(start, end)?,
+                }
+            };
+
+            match reason {
+                StopReason::Signal(signal) if self.pass_signals.contains(&signal) => {
+                    debug!("passing {} to the target", signal);
+                    self.target.deliver_signal(signal);
+                }
+                StopReason::Trap => {
+                    // range steps handle the breakpoints in the range
+                    // themselves
+                    let skip = match resume {
+                        Resume::StepRange { .. } => false,
+                        _ => self.skip_breakpoint(resume)?,
+                    };
+                    if skip {
+                        // a target continued at a breakpoint would hit it again
+                        step_over = matches!(resume, Resume::Continue);
+                    } else if stepped_over {
+                        // the step may have ended at another breakpoint
+                        let pc = T::Target::pc(&self.target.read_registers());
+                        if pc.is_some_and(|pc| self.at_breakpoint(pc)) {
+                            break StopReason::Trap;
+                        }
+                    } else {
+                        break StopReason::Trap;
+                    }
+                }
+                reason => break reason,
+            }
+        };
+
+        match reason {
+            StopReason::Trap => self.write_stop_reply(Signal::SIGTRAP, ""),
+            StopReason::Signal(signal) => self.write_stop_reply(signal, ""),
+            StopReason::HistoryBegin => self.write_stop_reply(Signal::SIGTRAP, "replaylog:begin;"),
+            StopReason::HistoryEnd => self.write_stop_reply(Signal::SIGTRAP, "replaylog:end;"),
+            StopReason::LibrariesChanged => self.write_stop_reply(Signal::SIGTRAP, "library:;"),
+            StopReason::FileIo(request) => {
+                // the debugger will access target memory and reply with an `F`
+                // packet, after which the target is resumed
+                self.file_io_resume = Some(resume);
+
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(request.to_string().as_bytes()).map_err(Error::comm)?;
+                resp.finish()
+            }
+        }
+    }
+
+    /// Single-steps the target until the PC leaves the range `start..end` or
+    /// the target stops on its own.
+    ///
+    /// Breakpoints and tracepoints hit in the range are handled like when
+    /// continuing, so the stepping only stops at breakpoints whose condition
+    /// is true.
+    fn step_range(&mut self, start: u64, end: u64) -> Result<StopReason, Error> {
+        loop {
+            let pc = T::Target::pc(&self.target.read_registers());
+            let reason = if pc.is_some_and(|pc| self.at_breakpoint(pc) || self.tracepoint_installed(pc)) {
+                self.step_over_breakpoint()
+            } else {
+                self.target.step(&mut Console::new(&mut self.comm))
+            };
+
+            let new_pc = T::Target::pc(&self.target.read_registers());
+            match (reason, new_pc) {
+                (StopReason::Trap, Some(new_pc)) if new_pc >= start && new_pc < end && Some(new_pc) != pc => {
+                    let breakpoint = self.at_breakpoint(new_pc) || self.tracepoint_installed(new_pc);
+                    if breakpoint && !self.skip_breakpoint(Resume::StepRange { start, end })? {
+                        return Ok(StopReason::Trap);
+                    }
+                }
+                (reason, _) => return Ok(reason),
+            }
+        }
+    }
+
+    /// Handles a request to resume the target.
+    ///
+    /// The program counter is moved to `addr` first, if given. Then `signal`
+    /// is delivered, unless the debugger isn't allowed to deliver it (see
+    /// `QProgramSignals`).
+    fn handle_resume(&mut self, resume: Resume, signal: Option<Signal>, addr: Option<u64>) -> Result<(), Error> {
+        if let Some(addr) = addr {
+            let mut regs = self.target.read_registers();
+            if T::Target::set_pc(&mut regs, addr).is_err() {
+                warn!("target doesn't support setting the PC, can't resume at {:#x}", addr);
+                let mut resp = ResponseWriter::new(&mut self.comm)?;
+                resp.write_all(b"E01").map_err(Error::comm)?;
+                return resp.finish();
+            }
+            self.target.write_registers(regs);
+        }
+
+        if let Some(signal) = signal {
+            let allowed = match self.program_signals {
+                Some(ref signals) => signals.contains(&signal),
+                None => true,
+            };
+            if allowed {
+                self.target.deliver_signal(signal);
+            } else {
+                debug!("discarding {} not in program signals", signal);
+            }
+        }
+
+        self.resume(resume)
+    }
+
+    /// Handles the target stopping at a breakpoint while continuing or range
+    /// stepping.
+    ///
+    /// Returns whether the target should be resumed without reporting the
+    /// stop, which is the case if the breakpoint's conditions are all false,
+    /// or if it has commands (which are run here).
+    fn skip_breakpoint(&mut self, resume: Resume) -> Result<bool, Error> {
+        match resume {
+            Resume::Continue | Resume::ReverseContinue | Resume::StepRange { .. } => {}
+            _ => return Ok(false),
+        }
+        let forward = !matches!(resume, Resume::ReverseContinue);
+
+        let pc = match T::Target::pc(&self.target.read_registers()) {
+            Some(pc) => pc,
+            None => return Ok(false),
+        };
+        // tracepoints only collect while executing forward, and never stop
+        // the target
+        let traced = forward && self.collect_trace_frames(pc);
+        let bp = match self.breakpoints.iter().find(|bp| bp.addr == pc) {
+            Some(bp) => bp,
+            None => return Ok(traced),
+        };
+
+        let mut agent = Agent::new(&mut self.target);
+        let hit = bp.conditions.is_empty() || bp.conditions.iter().any(|cond| match agent.eval(cond) {
+            Ok(value) => value != 0,
+            Err(e) => {
+                // stop so that the user notices
+                warn!("failed to evaluate breakpoint condition at {:#x}: {}", pc, e);
+                true
+            }
+        });
+        if !hit {
+            return Ok(true);
+        }
+
+        // commands only run when executing forward
+        if bp.commands.is_empty() || !forward {
+            return Ok(false);
+        }
+        let result = bp.commands.iter().try_for_each(|cmd| agent.eval(cmd).map(|_| ()));
+        Console::new(&mut self.comm).print(&agent.take_output())?;
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                warn!("failed to run breakpoint command at {:#x}: {}", pc, e);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Returns whether the `length` bytes starting at `start` can be erased
+    /// or written as flash memory.
+    ///
+    /// The range mustn't wrap around the address space and, if the target
+    /// has a memory map, has to lie inside of a flash region.
+    fn is_flash(&mut self, start: u64, length: u64) -> bool {
+        if length != 0 && start.checked_add(length - 1).is_none() {
+            return false;
+        }
+
+        match self.target.memory_map() {
+            Some(regions) => regions.iter().any(|region| match region.kind {
+                MemoryKind::Flash { .. } => region.contains_range(start, length),
+                _ => false,
+            }),
+            None => true,
+        }
+    }
+
+    /// Returns the address of the first occurrence of `pattern` in the `len`
+    /// bytes of memory starting at `start`.
+    ///
+    /// The memory is read in chunks, overlapping by the length of the pattern.
+    fn search_memory(&mut self, start: u64, len: u64, pattern: &[u8]) -> Result<Option<u64>, ()> {
+        const SEARCH_CHUNK_SIZE: usize = 0x4000;
+
+        if pattern.is_empty() {
+            return Ok(Some(start));
+        }
+
+        let end = start.saturating_add(len);
+        let mut addr = start;
+        let mut buf = Vec::new();
+        loop {
+            let chunk = cmp::min((SEARCH_CHUNK_SIZE + pattern.len() - 1) as u64, end - addr) as usize;
+            if chunk < pattern.len() {
+                return Ok(None);
+            }
+
+            buf.resize(chunk, 0);
+            self.target.read_mem_range(addr, &mut buf)?;
+            if let Some(offset) = buf.windows(pattern.len()).position(|window| window == pattern) {
+                return Ok(Some(addr + offset as u64));
+            }
+            addr += (chunk - pattern.len() + 1) as u64;
+        }
+    }
+
+    /// Computes the CRC-32 of the `len` bytes of memory starting at `start`.
+    ///
+    /// The memory is read in chunks, and ranges that wrap around the address
+    /// space are rejected.
+    fn crc_memory(&mut self, start: u64, len: u64) -> Result<u32, ()> {
+        const CRC_CHUNK_SIZE: u64 = 0x4000;
+
+        let end = start.checked_add(len).ok_or(())?;
+        let mut crc = 0xffff_ffff;
+        let mut addr = start;
+        let mut buf = Vec::new();
+        while addr < end {
+            buf.resize(cmp::min(CRC_CHUNK_SIZE, end - addr) as usize, 0);
+            self.target.read_mem_range(addr, &mut buf)?;
+            crc = buf.iter().fold(crc, |crc, &byte| crc32_update(crc, byte));
+            addr += buf.len() as u64;
+        }
+        Ok(crc)
+    }
+
+    /// Collects trace frames for the tracepoints at `pc`, removing the
+    /// tracepoints if the trace run ends.
+    ///
+    /// Returns whether there is an active tracepoint at `pc`.
+    fn collect_trace_frames(&mut self, pc: u64) -> bool {
+        if !self.trace.is_running() {
+            return false;
+        }
+
+        let found = self.trace.hit(&mut self.target, pc);
+        if !self.trace.is_running() {
+            self.uninstall_tracepoints();
+        }
+        found
+    }
+
+    /// Inserts breakpoints at the enabled tracepoints, unless there already is
+    /// a breakpoint inserted by the debugger.
+    fn install_tracepoints(&mut self) -> Result<(), ()> {
+        let addrs = self.trace.addresses();
+        for (i, &addr) in addrs.iter().enumerate() {
+            if self.at_breakpoint(addr) {
+                continue;
+            }
+            if self.target.insert_breakpoint(addr, 0) != Some(Ok(())) {
+                warn!("failed to insert tracepoint at {:#x}", addr);
+                for &addr in &addrs[..i] {
+                    if !self.at_breakpoint(addr) {
+                        let _ = self.target.remove_breakpoint(addr, 0);
+                    }
+                }
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the breakpoints inserted by `install_tracepoints`.
+    fn uninstall_tracepoints(&mut self) {
+        for addr in self.trace.addresses() {
+            if !self.at_breakpoint(addr) {
+                let _ = self.target.remove_breakpoint(addr, 0);
+            }
+        }
+    }
+
+    /// Returns whether the stub inserted a breakpoint for a tracepoint at
+    /// `addr`.
+    fn tracepoint_installed(&self, addr: u64) -> bool {
+        self.trace.is_running() && self.trace.addresses().contains(&addr)
+    }
+
+    /// Returns whether a breakpoint is inserted at `addr`.
+    fn at_breakpoint(&self, addr: u64) -> bool {
+        self.breakpoints.iter().any(|bp| bp.addr == addr)
+    }
+
+    /// Executes the instruction at the breakpoint the target stopped at, so
+    /// that it can be continued without hitting the breakpoint again.
+    fn step_over_breakpoint(&mut self) -> StopReason {
+        let pc = T::Target::pc(&self.target.read_registers());
+        let bp = self.breakpoints.iter().find(|bp| Some(bp.addr) == pc).map(|bp| (bp.addr, bp.kind))
+            .or_else(|| pc.filter(|&pc| self.tracepoint_installed(pc)).map(|pc| (pc, 0)));
+
+        if let Some((addr, kind)) = bp {
+            let _ = self.target.remove_breakpoint(addr, kind);
+        }
+        let reason = self.target.step(&mut Console::new(&mut self.comm));
+        if let Some((addr, kind)) = bp {
+            let _ = self.target.insert_breakpoint(addr, kind);
+        }
+        reason
+    }
+
+    /// Sends a stop reply packet indicating that the current thread stopped
+    /// due to `signal`.
+    ///
+    /// `extra` contains additional `name:value;` pairs describing the stop.
+    fn write_stop_reply(&mut self, signal: Signal, extra: &str) -> Result<(), Error> {
+        let thread = self.target.current_thread();
+
+        let mut resp = ResponseWriter::new(&mut self.comm)?;
+        resp.write(b'T').map_err(Error::comm)?;
+        resp.write_hex(signal.0).map_err(Error::comm)?;
+        resp.write_all(extra.as_bytes()).map_err(Error::comm)?;
+        resp.write_all(b"thread:").map_err(Error::comm)?;
+        thread.write(&mut resp, self.multiprocess).map_err(Error::comm)?;
+        resp.write(b';').map_err(Error::comm)?;
+        resp.finish()
+    }
+
+    /// Returns the first thread of the target referred to by `thread`.
+    fn find_thread(&mut self, thread: ThreadId) -> Option<Tid> {
+        let current = self.target.current_thread();
+        if thread.matches(current) {
+            // prefer the current thread if "any" thread is requested
+            return Some(current);
+        }
+
+        self.target.threads().into_iter().find(|tid| thread.matches(*tid))
+    }
+
+    /// Selects the thread to continue or step, if the debugger requested a
+    /// specific one.
+    fn select_cont_step_thread(&mut self) {
+        let thread = self.thread_cont_step;
+        if thread.is_single() {
+            if let Some(tid) = self.find_thread(thread) {
+                self.target.set_thread(tid);
+            }
+        }
+    }
+
+    /// Returns the contents of a special data object readable with `qXfer`.
+    ///
+    /// Returns `Ok(None)` if the object isn't supported, and an error if the
+    /// annex is invalid.
+    fn xfer_object(&mut self, object: &str, annex: &str) -> Result<Option<Vec<u8>>, ()> {
+        let takes_annex = object == "features" || object == "exec-file";
+        if !takes_annex && !annex.is_empty() {
+            return Err(());
+        }
+
+        let data = match object {
+            "features" => {
+                if annex != "target.xml" {
+                    return Err(());
+                }
+                let regs = self.target.read_registers();
+                target_xml::<T::Target>(&regs)
+            }
+            "threads" => Some(self.threads_xml()),
+            "memory-map" => self.target.memory_map().map(|regions| memory_map_xml(&regions)),
+            "exec-file" => {
+                let pid = if annex.is_empty() {
+                    None
+                } else {
+                    Some(u32::from_str_radix(annex, 16).map_err(|_| ())?)
+                };
+                self.target.exec_file(pid)
+            }
+            "libraries" => self.target.libraries().map(|libraries| libraries_xml(&libraries)),
+            "libraries-svr4" => self.target.libraries_svr4().map(|list| libraries_svr4_xml(&list)),
+            _ => {
+                debug!("unsupported qXfer object '{}'", object);
+                None
+            }
+        };
+
+        Ok(data.map(String::into_bytes))
+    }
+
+    /// Builds the thread list XML sent in response to `qXfer:threads:read`.
+    fn threads_xml(&mut self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0"?><threads>"#);
+        for thread in self.target.threads() {
+            xml.push_str(&format!(r#"<thread id="{}""#, thread.to_protocol(self.multiprocess)));
+            if let Some(core) = self.target.thread_core(thread) {
+                xml.push_str(&format!(r#" core="{:x}""#, core));
+            }
+            if let Some(name) = self.target.thread_name(thread) {
+                xml.push_str(&format!(r#" name="{}""#, xml_escape(&name)));
+            }
+            xml.push('>');
+            if let Some(info) = self.target.thread_extra_info(thread) {
+                xml.push_str(&xml_escape(&info));
+            }
+            xml.push_str("</thread>");
+        }
+        xml.push_str("</threads>");
+        xml
+    }
+
+    /// Sends the chunk of `data` requested by a `qXfer` read command.
+    ///
+    /// The response is prefixed with `l` if it contains the end of the data,
+    /// and with `m` if there's more to read.
+    fn write_xfer_chunk(&mut self, data: &[u8], offset: u64, len: u64) -> Result<(), Error> {
+        let start = cmp::min(offset, data.len() as u64) as usize;
+        let end = cmp::min(offset.saturating_add(len), data.len() as u64) as usize;
+
+        let mut resp = ResponseWriter::new(&mut self.comm)?;
+        resp.write(if end == data.len() { b'l' } else { b'm' }).map_err(Error::comm)?;
+        resp.write_all_escaped(&data[start..end]).map_err(Error::comm)?;
+        resp.finish()?;
+        Ok(())
     }
 
     /// Reads a packet into `self.buf`.
@@ -327,7 +1847,7 @@ impl<C: Comm, T: StubCalls> GdbStub<C, T> {
     }
 
     fn write(&mut self, b: u8) -> Result<(), Error> {
-        self.comm.write(b).map_err(|e| Error::comm(e))
+        self.comm.write(b).map_err(Error::comm)
     }
 
     fn write_response<F>(&mut self, f: F) -> Result<(), Error>
@@ -0,0 +1,101 @@
+//! Reporting of the executable and loaded libraries.
+//!
+//! GDB uses this information to find and relocate the symbols of modules
+//! loaded by the target program.
+
+use utils::xml_escape;
+
+use std::fmt::{self, Write};
+
+/// A library loaded by the target program, reported in the generic library
+/// list format.
+#[derive(Debug, Clone)]
+pub struct Library {
+    /// Path of the library file, as seen by GDB.
+    pub name: String,
+    /// Address the first segment of the library is loaded at.
+    pub base: u64,
+}
+
+/// A library in the SVR4 format, as used by ELF-based systems with a dynamic
+/// linker.
+#[derive(Debug, Clone)]
+pub struct Svr4Library {
+    /// Path of the library file, as seen by GDB.
+    pub name: String,
+    /// Address of the dynamic linker's `link_map` entry for this library.
+    pub lm: u64,
+    /// The difference between the library's load address and the addresses
+    /// in its ELF file.
+    pub l_addr: u64,
+    /// Address of the library's dynamic section.
+    pub l_ld: u64,
+}
+
+/// The list of loaded libraries in the SVR4 format.
+#[derive(Debug, Clone, Default)]
+pub struct Svr4LibraryList {
+    /// Address of the `link_map` entry of the main executable, if known.
+    pub main_lm: Option<u64>,
+    /// The loaded libraries.
+    pub libraries: Vec<Svr4Library>,
+}
+
+/// Offsets by which the sections of the main executable are relocated.
+#[derive(Debug, Copy, Clone)]
+pub enum SectionOffsets {
+    /// Offsets of the individual sections.
+    Sections {
+        text: u64,
+        data: u64,
+        bss: u64,
+    },
+    /// Offsets of the text and data segments.
+    ///
+    /// If `data_seg` is `None`, the data segment follows the text segment at
+    /// its original distance.
+    Segments {
+        text_seg: u64,
+        data_seg: Option<u64>,
+    },
+}
+
+impl fmt::Display for SectionOffsets {
+    /// Formats the offsets as a `qOffsets` response.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SectionOffsets::Sections { text, data, bss } => write!(f, "Text={:x};Data={:x};Bss={:x}", text, data, bss),
+            SectionOffsets::Segments { text_seg, data_seg } => {
+                write!(f, "TextSeg={:x}", text_seg)?;
+                if let Some(data_seg) = data_seg {
+                    write!(f, ";DataSeg={:x}", data_seg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Builds the XML sent in response to `qXfer:libraries:read`.
+pub(crate) fn libraries_xml(libraries: &[Library]) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0"?><library-list version="1.0">"#);
+    for library in libraries {
+        write!(xml, r#"<library name="{}"><segment address="{:#x}"/></library>"#, xml_escape(&library.name), library.base).unwrap();
+    }
+    xml.push_str("</library-list>");
+    xml
+}
+
+/// Builds the XML sent in response to `qXfer:libraries-svr4:read`.
+pub(crate) fn libraries_svr4_xml(list: &Svr4LibraryList) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0"?><library-list-svr4 version="1.0""#);
+    if let Some(main_lm) = list.main_lm {
+        write!(xml, r#" main-lm="{:#x}""#, main_lm).unwrap();
+    }
+    xml.push('>');
+    for library in &list.libraries {
+        write!(xml, r#"<library name="{}" lm="{:#x}" l_addr="{:#x}" l_ld="{:#x}"/>"#, xml_escape(&library.name), library.lm, library.l_addr, library.l_ld).unwrap();
+    }
+    xml.push_str("</library-list-svr4>");
+    xml
+}
@@ -0,0 +1,132 @@
+//! Memory map reporting.
+//!
+//! GDB uses the memory map to tell RAM apart from ROM and flash memory, which
+//! affects how `load` writes to the target and which regions `info mem` lists.
+
+use std::fmt::Write;
+
+/// The type of a memory region.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Read-write memory.
+    Ram,
+    /// Read-only memory.
+    Rom,
+    /// Flash memory that has to be erased in blocks before it can be written.
+    Flash {
+        /// Size of an erase block in bytes.
+        block_size: u64,
+    },
+}
+
+/// A region in the target's memory map.
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryRegion {
+    /// Start address of the region.
+    pub start: u64,
+    /// Length of the region in bytes.
+    pub length: u64,
+    /// Type of memory in this region.
+    pub kind: MemoryKind,
+}
+
+impl MemoryRegion {
+    /// Creates a new memory region description.
+    pub fn new(start: u64, length: u64, kind: MemoryKind) -> Self {
+        Self { start, length, kind }
+    }
+
+    /// Returns whether `addr` lies inside of this region.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr - self.start < self.length
+    }
+
+    /// Returns whether the `length` bytes starting at `start` lie inside of
+    /// this region.
+    pub fn contains_range(&self, start: u64, length: u64) -> bool {
+        self.contains(start) && length <= self.length - (start - self.start)
+    }
+}
+
+/// Flash operations requested by the debugger.
+///
+/// These are batched until the debugger finishes programming with
+/// `vFlashDone`.
+#[derive(Debug, Default)]
+pub(crate) struct FlashBatch {
+    /// Address ranges to erase, as `(start, length)` pairs.
+    pub erases: Vec<(u64, u64)>,
+    /// Data to write, as `(start, data)` pairs. Contiguous writes are merged.
+    pub writes: Vec<(u64, Vec<u8>)>,
+}
+
+impl FlashBatch {
+    pub fn erase(&mut self, start: u64, length: u64) {
+        self.erases.push((start, length));
+    }
+
+    pub fn write(&mut self, start: u64, data: &[u8]) {
+        if let Some(&mut (last_start, ref mut last_data)) = self.writes.last_mut() {
+            if last_start.checked_add(last_data.len() as u64) == Some(start) {
+                last_data.extend_from_slice(data);
+                return;
+            }
+        }
+
+        self.writes.push((start, data.to_vec()));
+    }
+}
+
+/// Builds the memory map XML sent in response to `qXfer:memory-map:read`.
+pub(crate) fn memory_map_xml(regions: &[MemoryRegion]) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0"?><!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd"><memory-map>"#);
+    for region in regions {
+        match region.kind {
+            MemoryKind::Ram | MemoryKind::Rom => {
+                let ty = if region.kind == MemoryKind::Ram { "ram" } else { "rom" };
+                write!(xml, r#"<memory type="{}" start="{:#x}" length="{:#x}"/>"#, ty, region.start, region.length).unwrap();
+            }
+            MemoryKind::Flash { block_size } => {
+                write!(xml, r#"<memory type="flash" start="{:#x}" length="{:#x}"><property name="blocksize">{:#x}</property></memory>"#, region.start, region.length, block_size).unwrap();
+            }
+        }
+    }
+    xml.push_str("</memory-map>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_contains_range() {
+        let region = MemoryRegion::new(0x1000, 0x100, MemoryKind::Ram);
+        assert!(region.contains_range(0x1000, 0x100));
+        assert!(region.contains_range(0x10ff, 1));
+        assert!(!region.contains_range(0x10ff, 2));
+        assert!(!region.contains_range(0xfff, 1));
+        assert!(!region.contains_range(0x1080, u64::MAX));
+
+        let top = MemoryRegion::new(!0xff, 0x100, MemoryKind::Ram);
+        assert!(top.contains_range(!0, 1));
+    }
+
+    #[test]
+    fn flash_batch_merges_contiguous_writes() {
+        let mut batch = FlashBatch::default();
+        batch.write(0x100, &[1, 2]);
+        batch.write(0x102, &[3]);
+        batch.write(0x200, &[4]);
+        batch.write(0x201, &[5]);
+        // not contiguous with the previous write
+        batch.write(0x100, &[6]);
+        assert_eq!(batch.writes, [(0x100, vec![1, 2, 3]), (0x200, vec![4, 5]), (0x100, vec![6])]);
+
+        // the end of the address space doesn't wrap around to 0
+        let mut batch = FlashBatch::default();
+        batch.write(!1, &[1, 2]);
+        batch.write(0, &[3]);
+        assert_eq!(batch.writes, [(!1, vec![1, 2]), (0, vec![3])]);
+    }
+}
@@ -0,0 +1,97 @@
+use hostio::OpenFlags;
+use utils::{hex_decode_in_place, unescape_in_place};
+use super::ParseError;
+
+use std::str;
+
+/// A host I/O command (`vFile:operation:arguments`).
+#[derive(Debug)]
+pub enum HostIoCommand<'a> {
+    /// `vFile:open:filename,flags,mode`
+    Open {
+        filename: &'a str,
+        flags: OpenFlags,
+        mode: u32,
+    },
+    /// `vFile:close:fd`
+    Close {
+        fd: u32,
+    },
+    /// `vFile:pread:fd,count,offset`
+    Pread {
+        fd: u32,
+        count: u64,
+        offset: u64,
+    },
+    /// `vFile:pwrite:fd,offset,data`
+    Pwrite {
+        fd: u32,
+        offset: u64,
+        /// The (unescaped) bytes to write.
+        data: &'a [u8],
+    },
+    /// `vFile:fstat:fd`
+    Fstat {
+        fd: u32,
+    },
+    /// `vFile:unlink:filename`
+    Unlink {
+        filename: &'a str,
+    },
+    /// `vFile:readlink:filename`
+    Readlink {
+        filename: &'a str,
+    },
+    /// `vFile:setfs:pid`
+    Setfs {
+        pid: u32,
+    },
+}
+
+impl<'a> HostIoCommand<'a> {
+    /// Parses the part of a `vFile` packet following `vFile:`.
+    pub fn parse(buf: &'a mut [u8]) -> Result<Self, ParseError> {
+        let mut parts = buf.splitn_mut(2, |b| *b == b':');
+        let op = str::from_utf8(parts.next().unwrap())?;
+        let args = parts.next().ok_or(ParseError::Malformed)?;
+
+        match op {
+            "open" => {
+                let mut parts = args.splitn_mut(3, |b| *b == b',');
+                let filename = str::from_utf8(hex_decode_in_place(parts.next().unwrap())?)?;
+                let flags = OpenFlags(parse_hex(parts.next())? as u32);
+                let mode = parse_hex(parts.next())? as u32;
+                Ok(HostIoCommand::Open { filename, flags, mode })
+            }
+            "close" => Ok(HostIoCommand::Close { fd: parse_hex(Some(args))? as u32 }),
+            "pread" => {
+                let mut parts = args.splitn(3, |b| *b == b',');
+                let fd = parse_hex(parts.next())? as u32;
+                let count = parse_hex(parts.next())?;
+                let offset = parse_hex(parts.next())?;
+                Ok(HostIoCommand::Pread { fd, count, offset })
+            }
+            "pwrite" => {
+                // the data is binary and may contain commas
+                let mut parts = args.splitn_mut(3, |b| *b == b',');
+                let fd = parse_hex(parts.next())? as u32;
+                let offset = parse_hex(parts.next())?;
+                let data = unescape_in_place(parts.next().ok_or(ParseError::Malformed)?);
+                Ok(HostIoCommand::Pwrite { fd, offset, data })
+            }
+            "fstat" => Ok(HostIoCommand::Fstat { fd: parse_hex(Some(args))? as u32 }),
+            "unlink" => Ok(HostIoCommand::Unlink { filename: str::from_utf8(hex_decode_in_place(args)?)? }),
+            "readlink" => Ok(HostIoCommand::Readlink { filename: str::from_utf8(hex_decode_in_place(args)?)? }),
+            "setfs" => Ok(HostIoCommand::Setfs { pid: parse_hex(Some(args))? as u32 }),
+            _ => {
+                debug!("unsupported host I/O operation '{}'", op);
+                Err(ParseError::Unsupported)
+            }
+        }
+    }
+}
+
+fn parse_hex<B: AsRef<[u8]>>(part: Option<B>) -> Result<u64, ParseError> {
+    let part = part.ok_or(ParseError::Malformed)?;
+    Ok(u64::from_str_radix(str::from_utf8(part.as_ref())?, 16)?)
+}
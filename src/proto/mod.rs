@@ -1,6 +1,15 @@
-use utils::{hex_decode_in_place, HexDecodeError};
+mod hostio;
 
-use std::{str, u64};
+pub use self::hostio::HostIoCommand;
+
+use fileio::FileIoReply;
+use hostio::Errno;
+use signal::Signal;
+use tid::Tid;
+use trace::{FrameSelector, TraceAction};
+use utils::{hex_decode_in_place, unescape_in_place, HexDecodeError};
+
+use std::str;
 use std::str::Utf8Error;
 use std::num::{ParseIntError, NonZeroU32};
 
@@ -27,6 +36,31 @@ pub enum Command<'a> {
     },
     /// `k` - Kill target program or system and disconnect.
     Kill,
+    /// `!` - Enable extended mode.
+    ExtendedMode,
+    /// `R` - Restart the target program (extended mode only).
+    Restart,
+    /// `vRun` - Run a new program (extended mode only).
+    Run {
+        /// The program to run. If `None`, the previously run program should
+        /// be restarted.
+        filename: Option<&'a str>,
+        /// Arguments passed to the program.
+        args: Vec<&'a str>,
+    },
+    /// `vAttach` - Attach to an existing process (extended mode only).
+    Attach {
+        pid: u32,
+    },
+    /// `vKill` - Kill a process without disconnecting (extended mode only).
+    KillProcess {
+        pid: u32,
+    },
+    /// `D` - Detach from the target program or system and disconnect.
+    Detach {
+        /// The process to detach from (`D;pid`).
+        pid: Option<u32>,
+    },
     /// `m` - Read data from memory.
     ReadMem {
         // FIXME: Replace `u64` with something... better... dunno.
@@ -40,18 +74,187 @@ pub enum Command<'a> {
         /// The bytes to write to memory.
         bytes: &'a [u8],
     },
+    /// `Z0` - Insert a software breakpoint.
+    InsertBreakpoint {
+        addr: u64,
+        /// Architecture-specific breakpoint kind, usually the size of the
+        /// breakpoint instruction.
+        kind: u64,
+        /// Agent expression bytecode of the conditions. The breakpoint only
+        /// stops the target if any of them is true.
+        conditions: Vec<&'a [u8]>,
+        /// Agent expression bytecode of commands to run when the breakpoint
+        /// is hit, instead of reporting the stop to the debugger.
+        commands: Vec<&'a [u8]>,
+        /// Whether the commands should keep running after the debugger
+        /// disconnects.
+        persist: bool,
+    },
+    /// `z0` - Remove a software breakpoint.
+    RemoveBreakpoint {
+        addr: u64,
+        kind: u64,
+    },
     /// `H` - Set the active thread for an action.
     SetThread {
         action: ThreadAction,
         thread: ThreadId,
     },
-    /// `c` - Continue execution.
+    /// `c` / `C` - Continue execution, optionally delivering a signal.
+    Continue {
+        signal: Option<Signal>,
+        /// Address to resume execution at.
+        addr: Option<u64>,
+    },
+    /// `s` / `S` - Execute the next instruction, then return.
+    Step {
+        signal: Option<Signal>,
+        /// Address of the instruction to execute.
+        addr: Option<u64>,
+    },
+    /// `vCont?` - Query the supported `vCont` actions.
+    VContQuery,
+    /// `vCont` - Resume threads with individual actions.
+    VCont {
+        actions: Vec<VContAction>,
+    },
+    /// `bc` - Continue execution backwards.
+    ReverseContinue,
+    /// `bs` - Undo the last executed instruction.
+    ReverseStep,
+    /// `QPassSignals` - Set the signals to deliver without stopping.
+    PassSignals {
+        signals: Vec<Signal>,
+    },
+    /// `QProgramSignals` - Set the signals the debugger may deliver.
+    ProgramSignals {
+        signals: Vec<Signal>,
+    },
+    /// `QTinit` - Delete all tracepoints and trace state variables.
+    TraceInit,
+    /// `QTDP:n:...` - Define a tracepoint.
+    DefineTracepoint {
+        number: u32,
+        addr: u64,
+        enabled: bool,
+        /// Number of instructions to single-step after a hit.
+        step_count: u64,
+        pass_count: u64,
+        /// Agent expression bytecode of the condition.
+        condition: Option<&'a [u8]>,
+    },
+    /// `QTDP:-n:...` - Add an action to a tracepoint.
+    TracepointAction {
+        number: u32,
+        addr: u64,
+        /// Whether the action is part of the `while-stepping` actions.
+        stepping: bool,
+        action: TraceAction,
+    },
+    /// `QTDV` - Define a trace state variable.
+    DefineTraceVariable {
+        number: u32,
+        initial: i64,
+    },
+    /// `QTro` - Set the memory that can be read while looking at a trace
+    /// frame.
+    TraceReadOnly {
+        regions: Vec<(u64, u64)>,
+    },
+    /// `QTStart` - Start a trace run.
+    TraceStart,
+    /// `QTStop` - Stop the trace run.
+    TraceStop,
+    /// `qCRC` - Compute the CRC-32 of a memory range.
+    Crc {
+        start: u64,
+        len: u64,
+    },
+    /// `qSearch:memory` - Search memory for a byte pattern.
+    SearchMemory {
+        start: u64,
+        len: u64,
+        pattern: &'a [u8],
+    },
+    /// `qTStatus` - Query the status of the trace run.
+    TraceStatus,
+    /// `QTFrame` - Select a trace frame.
+    SelectTraceFrame(FrameSelector),
+    /// `qTV` - Query the value of a trace state variable.
+    TraceVariableValue {
+        number: u32,
+    },
+    /// `vFlashErase` - Erase a range of flash memory.
+    FlashErase {
+        start: u64,
+        length: u64,
+    },
+    /// `vFlashWrite` - Write data to flash memory.
+    FlashWrite {
+        /// Start address to be written.
+        start: u64,
+        /// The (unescaped) bytes to write.
+        data: &'a [u8],
+    },
+    /// `vFlashDone` - Finish a flash programming sequence.
+    FlashDone,
+    /// `vFile` - Perform a file operation on the target side.
+    HostIo(HostIoCommand<'a>),
+    /// `qOffsets` - Query the relocation offsets of the executable.
+    SectionOffsets,
+    /// `qSymbol` - Symbol lookup handshake.
     ///
-    /// Note that this command can specify an optional address to start
-    /// execution at. This is not yet implemented.
-    Continue,
-    /// `s` - Execute the next instruction, then return.
-    Step,
+    /// `qSymbol::` notifies the stub that GDB is ready to look up symbols.
+    /// Otherwise, this contains the result of a lookup requested by the stub.
+    Symbol {
+        /// The symbol's address, if GDB found the symbol.
+        value: Option<u64>,
+        /// The (hex-decoded) name of the symbol that was looked up.
+        name: Option<&'a str>,
+    },
+    /// `qRcmd` - Run a `monitor` command.
+    Monitor {
+        /// The (hex-decoded) command text.
+        cmd: &'a str,
+    },
+    /// `T` - Check whether a thread is alive.
+    ThreadAlive {
+        thread: ThreadId,
+    },
+    /// `qC` - Query the current thread.
+    CurrentThread,
+    /// `qfThreadInfo` - Start listing the active threads.
+    ThreadInfoFirst,
+    /// `qsThreadInfo` - Continue listing the active threads.
+    ThreadInfoNext,
+    /// `qThreadExtraInfo` - Query a description of a thread.
+    ThreadExtraInfo {
+        thread: ThreadId,
+    },
+    /// `qAttached` - Query whether the stub attached to an existing process
+    /// (or created a new one).
+    Attached {
+        pid: Option<u32>,
+    },
+    /// `F` - Result of a File-I/O request made by the stub.
+    FileIoReply(FileIoReply),
+    /// `qSupported` - Report the features supported by the stub.
+    QuerySupported {
+        /// Features supported by the debugger (eg. `multiprocess+`).
+        features: Vec<&'a str>,
+    },
+    /// `qXfer:object:read:annex:offset,length` - Read a chunk of a special
+    /// data object.
+    ReadXfer {
+        /// The object to read (eg. `features`).
+        object: &'a str,
+        /// Object-specific name of the data to read (eg. `target.xml`).
+        annex: &'a str,
+        /// Offset of the requested chunk.
+        offset: u64,
+        /// Maximum length of the requested chunk.
+        len: u64,
+    },
 }
 
 impl<'a> Command<'a> {
@@ -64,16 +267,142 @@ impl<'a> Command<'a> {
 
         match buf[0] {
             b'v' => {
-                let name = buf[1..].splitn(2, |b| *b == b';').next().ok_or(ParseError::Malformed)?;
-                let name = str::from_utf8(name)?;
+                let mut parts = buf[1..].splitn_mut(2, |b| *b == b';' || *b == b':');
+                let name = str::from_utf8(parts.next().unwrap())?;
+                let args = parts.next().unwrap_or(&mut []);
                 trace!("v{}", name);
                 match name {
+                    "FlashErase" => {
+                        let mut parts = args.splitn(2, |b| *b == b',');
+                        let start = u64::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?;
+                        let length = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                        Ok(Command::FlashErase { start, length })
+                    }
+                    "FlashWrite" => {
+                        let mut parts = args.splitn_mut(2, |b| *b == b':');
+                        let start = u64::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?;
+                        let data = unescape_in_place(parts.next().ok_or(ParseError::Malformed)?);
+                        Ok(Command::FlashWrite { start, data })
+                    }
+                    "FlashDone" => Ok(Command::FlashDone),
+                    "Cont?" => Ok(Command::VContQuery),
+                    "Cont" => {
+                        let actions = args.split(|b| *b == b';')
+                            .map(VContAction::parse)
+                            .collect::<Result<_, _>>()?;
+                        Ok(Command::VCont { actions })
+                    }
+                    "File" => Ok(Command::HostIo(HostIoCommand::parse(args)?)),
+                    "Run" => {
+                        // vRun;filename;arg1;arg2... - all hex-encoded
+                        let mut parts = args.split_mut(|b| *b == b';');
+                        let filename = str::from_utf8(hex_decode_in_place(parts.next().unwrap())?)?;
+                        let filename = if filename.is_empty() { None } else { Some(filename) };
+                        let args = parts
+                            .map(|arg| Ok(str::from_utf8(hex_decode_in_place(arg)?)?))
+                            .collect::<Result<_, ParseError>>()?;
+                        Ok(Command::Run { filename, args })
+                    }
+                    "Attach" => {
+                        let pid = u32::from_str_radix(str::from_utf8(args)?, 16)?;
+                        Ok(Command::Attach { pid })
+                    }
+                    "Kill" => {
+                        let pid = u32::from_str_radix(str::from_utf8(args)?, 16)?;
+                        Ok(Command::KillProcess { pid })
+                    }
                     _ => {
                         debug!("unsupported v-command 'v{}'", name);
                         Err(ParseError::Unsupported)
                     }
                 }
             }
+            b'q' => {
+                let mut parts = buf[1..].splitn_mut(2, |b| *b == b':' || *b == b',');
+                let name = str::from_utf8(parts.next().unwrap())?;
+                let args = parts.next().unwrap_or(&mut []);
+                match name {
+                    "Supported" => {
+                        let features = args
+                            .split(|b| *b == b';')
+                            .filter(|feature| !feature.is_empty())
+                            .map(str::from_utf8)
+                            .collect::<Result<_, _>>()?;
+                        Ok(Command::QuerySupported { features })
+                    }
+                    "C" => Ok(Command::CurrentThread),
+                    "Offsets" => Ok(Command::SectionOffsets),
+                    "CRC" => {
+                        // qCRC:addr,length
+                        let mut parts = args.splitn(2, |b| *b == b',');
+                        let start = u64::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?;
+                        let len = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                        Ok(Command::Crc { start, len })
+                    }
+                    "Symbol" => {
+                        // qSymbol:value:name
+                        let mut parts = args.splitn_mut(2, |b| *b == b':');
+                        let value = parts.next().unwrap();
+                        let value = if value.is_empty() {
+                            None
+                        } else {
+                            Some(u64::from_str_radix(str::from_utf8(value)?, 16)?)
+                        };
+                        let name = str::from_utf8(hex_decode_in_place(parts.next().ok_or(ParseError::Malformed)?)?)?;
+                        let name = if name.is_empty() { None } else { Some(name) };
+                        Ok(Command::Symbol { value, name })
+                    }
+                    "fThreadInfo" => Ok(Command::ThreadInfoFirst),
+                    "sThreadInfo" => Ok(Command::ThreadInfoNext),
+                    "Search" => {
+                        // qSearch:memory:addr;len;pattern
+                        if !args.starts_with(b"memory:") {
+                            return Err(ParseError::Unsupported);
+                        }
+                        let args = &mut args[b"memory:".len()..];
+                        let mut parts = args.splitn_mut(3, |b| *b == b';');
+                        let start = u64::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?;
+                        let len = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                        let pattern = unescape_in_place(parts.next().ok_or(ParseError::Malformed)?);
+                        Ok(Command::SearchMemory { start, len, pattern })
+                    }
+                    "TStatus" => Ok(Command::TraceStatus),
+                    "TV" => Ok(Command::TraceVariableValue { number: u32::from_str_radix(str::from_utf8(args)?, 16)? }),
+                    "ThreadExtraInfo" => Ok(Command::ThreadExtraInfo { thread: ThreadId::parse(args)? }),
+                    "Attached" => {
+                        let pid = if args.is_empty() {
+                            None
+                        } else {
+                            Some(u32::from_str_radix(str::from_utf8(args)?, 16)?)
+                        };
+                        Ok(Command::Attached { pid })
+                    }
+                    "Rcmd" => {
+                        let cmd = str::from_utf8(hex_decode_in_place(args)?)?;
+                        Ok(Command::Monitor { cmd })
+                    }
+                    "Xfer" => {
+                        // qXfer:object:read:annex:offset,length
+                        let mut parts = args.splitn(4, |b| *b == b':');
+                        let object = str::from_utf8(parts.next().unwrap())?;
+                        let op = parts.next().ok_or(ParseError::Malformed)?;
+                        if op != b"read" {
+                            debug!("unsupported qXfer operation '{}'", String::from_utf8_lossy(op));
+                            return Err(ParseError::Unsupported);
+                        }
+                        let annex = str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?;
+                        let mut range = parts.next().ok_or(ParseError::Malformed)?.splitn(2, |b| *b == b',');
+                        let offset = u64::from_str_radix(str::from_utf8(range.next().unwrap())?, 16)?;
+                        let len = u64::from_str_radix(str::from_utf8(range.next().ok_or(ParseError::Malformed)?)?, 16)?;
+
+                        Ok(Command::ReadXfer { object, annex, offset, len })
+                    }
+                    _ => {
+                        debug!("unsupported query 'q{}'", name);
+                        Err(ParseError::Unsupported)
+                    }
+                }
+            }
             m @ b'm' | m @ b'M' => {
                 let mut parts = buf[1..].splitn_mut(3, |b| *b == b',' || *b == b':');
                 let start = u64::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?;
@@ -83,7 +412,7 @@ impl<'a> Command<'a> {
                     Ok(Command::ReadMem { start, len })
                 } else {
                     // hex-decode the bytes to be written
-                    let mut bytes = parts.next().ok_or(ParseError::Malformed)?;
+                    let bytes = parts.next().ok_or(ParseError::Malformed)?;
                     // do a little trick to reuse the buffer we were passed
                     // store the decoded bytes in the first part of `bytes`
                     // while decoding 2 bytes (hex digits) at a time
@@ -97,6 +426,51 @@ impl<'a> Command<'a> {
                     Ok(Command::WriteMem { start, bytes })
                 }
             }
+            z @ b'Z' | z @ b'z' => {
+                // Ztype,addr,kind[;cond_list][;cmds:persist,cmd_list]
+                let mut params = buf[1..].splitn_mut(2, |b| *b == b';');
+                let mut parts = params.next().unwrap().splitn(3, |b| *b == b',');
+                if parts.next() != Some(b"0") {
+                    return Err(ParseError::Unsupported);
+                }
+                let addr = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                let kind = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+
+                if z == b'z' {
+                    return Ok(Command::RemoveBreakpoint { addr, kind });
+                }
+
+                let mut conditions = Vec::new();
+                let mut commands = Vec::new();
+                let mut persist = false;
+                // expressions following `cmds:` are commands, the ones before
+                // are conditions
+                let mut in_commands = false;
+                let mut options = params.next().unwrap_or(&mut []);
+                while let Some(&first) = options.first() {
+                    options = match first {
+                        b';' => &mut options[1..],
+                        b'X' => {
+                            // several expressions aren't separated
+                            let (expr, rest) = parse_agent_expr(options)?;
+                            if in_commands {
+                                commands.push(expr);
+                            } else {
+                                conditions.push(expr);
+                            }
+                            rest
+                        }
+                        _ if options.starts_with(b"cmds:") && options.get(6) == Some(&b',') => {
+                            in_commands = true;
+                            persist = options[5] != b'0';
+                            &mut options[7..]
+                        }
+                        _ => return Err(ParseError::Malformed),
+                    };
+                }
+
+                Ok(Command::InsertBreakpoint { addr, kind, conditions, commands, persist })
+            }
             b'H' => {
                 let action = match buf[1] as char {
                     'c' => ThreadAction::ContStep,
@@ -110,19 +484,92 @@ impl<'a> Command<'a> {
 
                 Ok(Command::SetThread { action, thread })
             }
-            b'c' => {
-                if buf.len() > 1 {
-                    return Err(ParseError::Unsupported);
+            b'c' | b's' => {
+                // c[addr]
+                let addr = if buf.len() > 1 {
+                    Some(u64::from_str_radix(str::from_utf8(&buf[1..])?, 16)?)
+                } else {
+                    None
+                };
+
+                if buf[0] == b'c' {
+                    Ok(Command::Continue { signal: None, addr })
+                } else {
+                    Ok(Command::Step { signal: None, addr })
                 }
+            }
+            b'b' => match &buf[1..] {
+                b"c" => Ok(Command::ReverseContinue),
+                b"s" => Ok(Command::ReverseStep),
+                _ => Err(ParseError::Unsupported),
+            },
+            b'C' | b'S' => {
+                // Csig[;addr]
+                let mut parts = buf[1..].splitn(2, |b| *b == b';');
+                let signal = Some(Signal(u8::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?));
+                let addr = match parts.next() {
+                    Some(addr) => Some(u64::from_str_radix(str::from_utf8(addr)?, 16)?),
+                    None => None,
+                };
 
-                Ok(Command::Continue)
+                if buf[0] == b'C' {
+                    Ok(Command::Continue { signal, addr })
+                } else {
+                    Ok(Command::Step { signal, addr })
+                }
             }
-            b's' => {
-                if buf.len() > 1 {
-                    return Err(ParseError::Unsupported);
+            b'Q' => {
+                let mut parts = buf[1..].splitn_mut(2, |b| *b == b':');
+                let name = str::from_utf8(parts.next().unwrap())?;
+                let args = parts.next().unwrap_or(&mut []);
+                match name {
+                    "PassSignals" => Ok(Command::PassSignals { signals: parse_signal_list(args)? }),
+                    "ProgramSignals" => Ok(Command::ProgramSignals { signals: parse_signal_list(args)? }),
+                    "Tinit" => Ok(Command::TraceInit),
+                    "TStart" => Ok(Command::TraceStart),
+                    "TStop" => Ok(Command::TraceStop),
+                    "TDP" => parse_tracepoint(args),
+                    "TDV" => {
+                        // QTDV:n:value:builtin:name
+                        let mut parts = args.split(|b| *b == b':');
+                        let number = u32::from_str_radix(str::from_utf8(parts.next().unwrap())?, 16)?;
+                        let initial = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                        Ok(Command::DefineTraceVariable { number, initial: initial as i64 })
+                    }
+                    "Tro" => {
+                        // QTro:start,end:start,end...
+                        let regions = args.split(|b| *b == b':')
+                            .filter(|region| !region.is_empty())
+                            .map(|region| {
+                                let mut range = region.splitn(2, |b| *b == b',');
+                                let start = u64::from_str_radix(str::from_utf8(range.next().unwrap())?, 16)?;
+                                let end = u64::from_str_radix(str::from_utf8(range.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                                Ok((start, end))
+                            })
+                            .collect::<Result<_, ParseError>>()?;
+                        Ok(Command::TraceReadOnly { regions })
+                    }
+                    "TFrame" => {
+                        let mut parts = args.split(|b| *b == b':');
+                        let first = str::from_utf8(parts.next().unwrap())?;
+                        let mut hex = || -> Result<u64, ParseError> {
+                            Ok(u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?)
+                        };
+                        let selector = match first {
+                            "-1" => FrameSelector::None,
+                            "pc" => FrameSelector::Pc(hex()?),
+                            "tdp" => FrameSelector::Tracepoint(hex()? as u32),
+                            "range" => FrameSelector::Range { start: hex()?, end: hex()? },
+                            "outside" => FrameSelector::Outside { start: hex()?, end: hex()? },
+                            n => FrameSelector::Number(usize::from_str_radix(n, 16)?),
+                        };
+                        Ok(Command::SelectTraceFrame(selector))
+                    }
+                    _ => {
+                        debug!("unsupported Q-command 'Q{}'", name);
+                        Err(ParseError::Unsupported)
+                    }
                 }
-
-                Ok(Command::Step)
             }
             b'G' => {
                 // hex-decode the rest of `buf`
@@ -132,7 +579,36 @@ impl<'a> Command<'a> {
             // FIXME reject trailing data
             b'?' => Ok(Command::GetHaltReason),
             b'g' => Ok(Command::ReadRegisters),
+            b'T' => Ok(Command::ThreadAlive { thread: ThreadId::parse(&buf[1..])? }),
             b'k' => Ok(Command::Kill),
+            b'F' => {
+                // Fretcode,errno,C;attachment
+                let body = buf[1..].split(|b| *b == b';').next().unwrap();
+                let mut parts = body.split(|b| *b == b',');
+                let result = str::from_utf8(parts.next().unwrap())?;
+                let result = match result.strip_prefix('-') {
+                    Some(abs) => -i64::from_str_radix(abs, 16)?,
+                    None => i64::from_str_radix(result, 16)?,
+                };
+                let errno = match parts.next() {
+                    Some(errno) => Some(Errno(u32::from_str_radix(str::from_utf8(errno)?, 16)?)),
+                    None => None,
+                };
+                let ctrl_c = parts.next() == Some(b"C");
+
+                Ok(Command::FileIoReply(FileIoReply { result, errno, ctrl_c }))
+            }
+            b'!' => Ok(Command::ExtendedMode),
+            b'R' => Ok(Command::Restart),
+            b'D' => {
+                let pid = match buf.get(1) {
+                    None => None,
+                    Some(b';') => Some(u32::from_str_radix(str::from_utf8(&buf[2..])?, 16)?),
+                    Some(_) => return Err(ParseError::Malformed),
+                };
+
+                Ok(Command::Detach { pid })
+            }
             unknown => {
                 debug!("unsupported command '{}'", unknown as char);
                 Err(ParseError::Unsupported)
@@ -141,25 +617,223 @@ impl<'a> Command<'a> {
     }
 }
 
-#[derive(Debug)]
-pub enum ThreadId {
+/// An action of a `vCont` command.
+#[derive(Debug, Copy, Clone)]
+pub struct VContAction {
+    pub kind: VContKind,
+    /// The threads to apply the action to, or `None` for all threads without
+    /// an explicit action.
+    pub thread: Option<ThreadId>,
+}
+
+/// How a thread is resumed by `vCont`.
+#[derive(Debug, Copy, Clone)]
+pub enum VContKind {
+    /// `c` / `C` - Continue, optionally delivering a signal.
+    Continue(Option<Signal>),
+    /// `s` / `S` - Step, optionally delivering a signal.
+    Step(Option<Signal>),
+    /// `r` - Step while the PC is in the range `start..end`.
+    StepRange { start: u64, end: u64 },
+}
+
+impl VContAction {
+    /// Parses an `action[:thread-id]` pair.
+    fn parse(buf: &[u8]) -> Result<Self, ParseError> {
+        let mut parts = buf.splitn(2, |b| *b == b':');
+        let action = parts.next().unwrap();
+        let thread = match parts.next() {
+            Some(thread) => Some(ThreadId::parse(thread)?),
+            None => None,
+        };
+
+        let (&op, arg) = action.split_first().ok_or(ParseError::Malformed)?;
+        let kind = match op {
+            b'c' | b's' if !arg.is_empty() => return Err(ParseError::Malformed),
+            b'c' => VContKind::Continue(None),
+            b's' => VContKind::Step(None),
+            b'C' => VContKind::Continue(Some(Signal(u8::from_str_radix(str::from_utf8(arg)?, 16)?))),
+            b'S' => VContKind::Step(Some(Signal(u8::from_str_radix(str::from_utf8(arg)?, 16)?))),
+            b'r' => {
+                let mut range = arg.splitn(2, |b| *b == b',');
+                let start = u64::from_str_radix(str::from_utf8(range.next().unwrap())?, 16)?;
+                let end = u64::from_str_radix(str::from_utf8(range.next().ok_or(ParseError::Malformed)?)?, 16)?;
+                VContKind::StepRange { start, end }
+            }
+            _ => {
+                debug!("unsupported vCont action '{}'", op as char);
+                return Err(ParseError::Unsupported);
+            }
+        };
+
+        Ok(VContAction { kind, thread })
+    }
+}
+
+/// Parses and decodes an agent expression of the form `Xlen,expr`.
+///
+/// Returns the decoded bytecode and the data following the expression.
+fn parse_agent_expr(buf: &mut [u8]) -> Result<(&[u8], &mut [u8]), ParseError> {
+    let comma = buf.iter().position(|b| *b == b',').ok_or(ParseError::Malformed)?;
+    let len = usize::from_str_radix(str::from_utf8(&buf[1..comma])?, 16)?;
+    let hex = &mut buf[comma + 1..];
+    if hex.len() < len * 2 {
+        error!("agent expression len={}, but only {} hex digits", len, hex.len());
+        return Err(ParseError::Malformed);
+    }
+
+    let (expr, rest) = hex.split_at_mut(len * 2);
+    Ok((hex_decode_in_place(expr)?, rest))
+}
+
+/// Parses the arguments of a `QTDP` packet, which either defines a tracepoint
+/// (`n:addr:ena:step:pass[:Xlen,cond]`) or adds an action to it
+/// (`-n:addr:[S]action`).
+///
+/// A trailing `-` indicates that more actions follow; since the actions are
+/// added one packet at a time, it is ignored.
+fn parse_tracepoint(args: &mut [u8]) -> Result<Command<'_>, ParseError> {
+    let len = args.len() - args.ends_with(b"-") as usize;
+    let args = &mut args[..len];
+    let mut parts = args.splitn_mut(3, |b| *b == b':');
+    let number = str::from_utf8(parts.next().unwrap())?;
+    let addr = u64::from_str_radix(str::from_utf8(parts.next().ok_or(ParseError::Malformed)?)?, 16)?;
+    let rest = parts.next().ok_or(ParseError::Malformed)?;
+
+    if let Some(number) = number.strip_prefix('-') {
+        let number = u32::from_str_radix(number, 16)?;
+        let (stepping, action) = match rest.split_first_mut() {
+            Some((b'S', action)) => (true, action),
+            _ => (false, rest),
+        };
+        let action = match action.first() {
+            // the register mask is ignored, all registers are collected
+            Some(b'R') => TraceAction::Registers,
+            Some(b'M') => {
+                // Mbasereg,offset,len
+                let mut fields = action[1..].split(|b| *b == b',');
+                let mut hex = || -> Result<u64, ParseError> {
+                    Ok(u64::from_str_radix(str::from_utf8(fields.next().ok_or(ParseError::Malformed)?)?, 16)?)
+                };
+                let basereg = match hex()? {
+                    0xffff_ffff => None,
+                    reg => Some(reg as u16),
+                };
+                TraceAction::Memory { basereg, offset: hex()?, len: hex()? }
+            }
+            Some(b'X') => TraceAction::Expr(parse_agent_expr(action)?.0.to_vec()),
+            _ => {
+                debug!("unsupported tracepoint action '{}'", String::from_utf8_lossy(action));
+                return Err(ParseError::Unsupported);
+            }
+        };
+        return Ok(Command::TracepointAction { number, addr, stepping, action });
+    }
+
+    let number = u32::from_str_radix(number, 16)?;
+    let mut fields = rest.split_mut(|b| *b == b':');
+    let enabled = match &*fields.next().unwrap() {
+        b"E" => true,
+        b"D" => false,
+        _ => return Err(ParseError::Malformed),
+    };
+    let step_count = u64::from_str_radix(str::from_utf8(fields.next().ok_or(ParseError::Malformed)?)?, 16)?;
+    let pass_count = u64::from_str_radix(str::from_utf8(fields.next().ok_or(ParseError::Malformed)?)?, 16)?;
+
+    let mut condition = None;
+    for field in fields {
+        match field.first() {
+            Some(b'X') => condition = Some(parse_agent_expr(field)?.0),
+            _ => {
+                // eg. `F` for fast tracepoints
+                debug!("unsupported tracepoint option '{}'", String::from_utf8_lossy(field));
+                return Err(ParseError::Unsupported);
+            }
+        }
+    }
+
+    Ok(Command::DefineTracepoint { number, addr, enabled, step_count, pass_count, condition })
+}
+
+/// Parses a `;`-separated list of hex-encoded signal numbers.
+fn parse_signal_list(list: &[u8]) -> Result<Vec<Signal>, ParseError> {
+    if list.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    list.split(|b| *b == b';')
+        .map(|sig| Ok(Signal(u8::from_str_radix(str::from_utf8(sig)?, 16)?)))
+        .collect()
+}
+
+/// A process or thread ID sent by the debugger.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Id {
+    /// `-1` - All processes/threads.
     All,
+    /// `0` - An arbitrary process/thread.
     Any,
-    Thread(NonZeroU32),
+    /// A specific process/thread.
+    Specific(NonZeroU32),
 }
 
-impl ThreadId {
+impl Id {
     fn parse(buf: &[u8]) -> Result<Self, ParseError> {
         match buf {
-            b"-1" => Ok(ThreadId::All),
-            b"0" => Ok(ThreadId::Any),
+            b"-1" => Ok(Id::All),
+            b"0" => Ok(Id::Any),
             _ => {
-                // big-endian hex string indicating the thread ID
-                let id = u32::from_be(u32::from_str_radix(str::from_utf8(buf)?, 16)?);
-                Ok(ThreadId::Thread(NonZeroU32::new(id).ok_or(ParseError::Malformed)?))
+                let id = u32::from_str_radix(str::from_utf8(buf)?, 16)?;
+                Ok(Id::Specific(NonZeroU32::new(id).ok_or(ParseError::Malformed)?))
             }
         }
     }
+
+    fn matches(&self, id: u32) -> bool {
+        match *self {
+            Id::All | Id::Any => true,
+            Id::Specific(own) => own.get() == id,
+        }
+    }
+}
+
+/// A thread ID sent by the debugger, optionally qualified with a process ID
+/// (`pPID.TID`, when using the multiprocess extension).
+#[derive(Debug, Copy, Clone)]
+pub struct ThreadId {
+    pub pid: Option<Id>,
+    pub tid: Id,
+}
+
+impl ThreadId {
+    pub const ALL: ThreadId = ThreadId { pid: None, tid: Id::All };
+    pub const ANY: ThreadId = ThreadId { pid: None, tid: Id::Any };
+
+    pub fn parse(buf: &[u8]) -> Result<Self, ParseError> {
+        if buf.first() == Some(&b'p') {
+            let mut parts = buf[1..].splitn(2, |b| *b == b'.');
+            let pid = Id::parse(parts.next().unwrap())?;
+            // `pPID` refers to all threads of the process
+            let tid = match parts.next() {
+                Some(tid) => Id::parse(tid)?,
+                None => Id::All,
+            };
+            Ok(ThreadId { pid: Some(pid), tid })
+        } else {
+            Ok(ThreadId { pid: None, tid: Id::parse(buf)? })
+        }
+    }
+
+    /// Returns whether `thread` is one of the threads referred to by `self`.
+    pub fn matches(&self, thread: Tid) -> bool {
+        self.pid.map(|pid| pid.matches(thread.pid)).unwrap_or(true) && self.tid.matches(thread.tid)
+    }
+
+    /// Returns whether `self` refers to a single thread (or to an arbitrary
+    /// one) rather than to all threads.
+    pub fn is_single(&self) -> bool {
+        self.pid != Some(Id::All) && self.tid != Id::All
+    }
 }
 
 pub enum ParseError {
@@ -187,7 +861,184 @@ impl From<Utf8Error> for ParseError {
 }
 
 impl From<HexDecodeError> for ParseError {
-    fn from(_: HexDecodeError) -> Self {
+    fn from(e: HexDecodeError) -> Self {
+        debug!("{}", e);
         ParseError::Malformed
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hostio::OpenFlags;
+
+    fn id(id: u32) -> Id {
+        Id::Specific(NonZeroU32::new(id).unwrap())
+    }
+
+    #[test]
+    fn parse_thread_id() {
+        let thread = |buf: &[u8]| {
+            let thread = ThreadId::parse(buf).ok().unwrap();
+            (thread.pid, thread.tid)
+        };
+        assert_eq!(thread(b"1f"), (None, id(0x1f)));
+        assert_eq!(thread(b"-1"), (None, Id::All));
+        assert_eq!(thread(b"0"), (None, Id::Any));
+        assert_eq!(thread(b"p1.2"), (Some(id(1)), id(2)));
+        assert_eq!(thread(b"p-1.-1"), (Some(Id::All), Id::All));
+        assert_eq!(thread(b"p2.0"), (Some(id(2)), Id::Any));
+        // all threads of the process
+        assert_eq!(thread(b"p2"), (Some(id(2)), Id::All));
+
+        for buf in [&b"p"[..], b"p1.", b"p.1", b"p1.2.3", b"x"] {
+            assert!(ThreadId::parse(buf).is_err());
+        }
+    }
+
+    #[test]
+    fn thread_id_matches() {
+        let process = ThreadId::parse(b"p1").ok().unwrap();
+        assert!(process.matches(Tid { pid: 1, tid: 5 }));
+        assert!(!process.matches(Tid { pid: 2, tid: 5 }));
+        assert!(!process.is_single());
+
+        let thread = ThreadId::parse(b"p1.2").ok().unwrap();
+        assert!(thread.matches(Tid { pid: 1, tid: 2 }));
+        assert!(!thread.matches(Tid { pid: 1, tid: 3 }));
+        assert!(thread.is_single());
+
+        // without a pid, any process matches
+        let thread = ThreadId::parse(b"2").ok().unwrap();
+        assert!(thread.matches(Tid { pid: 3, tid: 2 }));
+        assert!(ThreadId::ANY.is_single());
+        assert!(!ThreadId::ALL.is_single());
+    }
+
+    #[test]
+    fn parse_host_io() {
+        // the data of `pwrite` may contain commas
+        let mut buf = *b"vFile:pwrite:3,10,a,}]";
+        match Command::parse(&mut buf) {
+            Ok(Command::HostIo(HostIoCommand::Pwrite { fd, offset, data })) => {
+                assert_eq!((fd, offset, data), (3, 0x10, &b"a,}"[..]));
+            }
+            _ => panic!("not parsed as vFile:pwrite"),
+        }
+
+        let mut buf = *b"vFile:open:2f746d70,241,1a4";
+        match Command::parse(&mut buf) {
+            Ok(Command::HostIo(HostIoCommand::Open { filename, flags, mode })) => {
+                assert_eq!((filename, flags, mode), ("/tmp", OpenFlags(0x241), 0o644));
+            }
+            _ => panic!("not parsed as vFile:open"),
+        }
+    }
+
+    #[test]
+    fn parse_file_io_reply() {
+        let reply = |packet: &[u8]| {
+            let mut buf = packet.to_vec();
+            match Command::parse(&mut buf) {
+                Ok(Command::FileIoReply(reply)) => (reply.result, reply.errno, reply.ctrl_c),
+                _ => panic!("not parsed as an F reply"),
+            }
+        };
+        assert_eq!(reply(b"F1a"), (0x1a, None, false));
+        assert_eq!(reply(b"F-1,9"), (-1, Some(Errno(9)), false));
+        assert_eq!(reply(b"F-1,4,C"), (-1, Some(Errno(4)), true));
+        // the attachment is ignored
+        assert_eq!(reply(b"F3;abc"), (3, None, false));
+
+        let mut buf = *b"F-x";
+        assert!(matches!(Command::parse(&mut buf), Err(ParseError::Malformed)));
+    }
+
+    #[test]
+    fn parse_vcont() {
+        let mut buf = *b"vCont;r1000,1010:p1.2;c";
+        match Command::parse(&mut buf) {
+            Ok(Command::VCont { actions }) => {
+                assert_eq!(actions.len(), 2);
+                assert!(matches!(actions[0].kind, VContKind::StepRange { start: 0x1000, end: 0x1010 }));
+                assert!(actions[0].thread.unwrap().matches(Tid { pid: 1, tid: 2 }));
+                assert!(matches!(actions[1].kind, VContKind::Continue(None)));
+                assert!(actions[1].thread.is_none());
+            }
+            _ => panic!("not parsed as vCont"),
+        }
+
+        let mut buf = *b"vCont;r1000";
+        assert!(matches!(Command::parse(&mut buf), Err(ParseError::Malformed)));
+    }
+
+    #[test]
+    fn parse_breakpoint() {
+        // a condition, then two commands
+        let mut buf = *b"Z0,1000,4;X2,2227;cmds:1,X1,27X3,220127";
+        match Command::parse(&mut buf) {
+            Ok(Command::InsertBreakpoint { addr, kind, conditions, commands, persist }) => {
+                assert_eq!((addr, kind, persist), (0x1000, 4, true));
+                assert_eq!(conditions, [&[0x22, 0x27][..]]);
+                assert_eq!(commands, [&[0x27][..], &[0x22, 0x01, 0x27][..]]);
+            }
+            _ => panic!("not parsed as a breakpoint"),
+        }
+
+        let mut buf = *b"Z0,1000,4;X2,22";
+        assert!(matches!(Command::parse(&mut buf), Err(ParseError::Malformed)));
+        let mut buf = *b"Z1,1000,4";
+        assert!(matches!(Command::parse(&mut buf), Err(ParseError::Unsupported)));
+    }
+
+    #[test]
+    fn parse_tracepoints() {
+        let mut buf = *b"QTDP:1:401000:E:0:3:X2,2227-";
+        match Command::parse(&mut buf) {
+            Ok(Command::DefineTracepoint { number, addr, enabled, step_count, pass_count, condition }) => {
+                assert_eq!((number, addr, enabled, step_count, pass_count), (1, 0x401000, true, 0, 3));
+                assert_eq!(condition, Some(&[0x22, 0x27][..]));
+            }
+            _ => panic!("not parsed as a tracepoint"),
+        }
+
+        let mut buf = *b"QTDP:-1:401000:M4,10,8";
+        match Command::parse(&mut buf) {
+            Ok(Command::TracepointAction { number: 1, addr: 0x401000, stepping: false, action }) => {
+                assert!(matches!(action, TraceAction::Memory { basereg: Some(4), offset: 0x10, len: 8 }));
+            }
+            _ => panic!("not parsed as a tracepoint action"),
+        }
+
+        let mut buf = *b"QTDP:-1:401000:SMffffffff,2000,4";
+        match Command::parse(&mut buf) {
+            Ok(Command::TracepointAction { stepping: true, action, .. }) => {
+                assert!(matches!(action, TraceAction::Memory { basereg: None, offset: 0x2000, len: 4 }));
+            }
+            _ => panic!("not parsed as a tracepoint action"),
+        }
+
+        let mut buf = *b"QTDP:1:401000:E:0:0:F4";
+        assert!(matches!(Command::parse(&mut buf), Err(ParseError::Unsupported)));
+    }
+
+    #[test]
+    fn parse_search() {
+        // the pattern contains an escaped `}`
+        let mut buf = *b"qSearch:memory:1000;200;ab}]";
+        match Command::parse(&mut buf) {
+            Ok(Command::SearchMemory { start, len, pattern }) => {
+                assert_eq!((start, len, pattern), (0x1000, 0x200, &b"ab}"[..]));
+            }
+            _ => panic!("not parsed as qSearch"),
+        }
+    }
+
+    #[test]
+    fn parse_crc() {
+        let mut buf = *b"qCRC:1000,20";
+        assert!(matches!(Command::parse(&mut buf), Ok(Command::Crc { start: 0x1000, len: 0x20 })));
+        let mut buf = *b"qCRC:1000";
+        assert!(matches!(Command::parse(&mut buf), Err(ParseError::Malformed)));
+    }
+}
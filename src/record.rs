@@ -0,0 +1,608 @@
+//! Execution recording for reverse debugging.
+//!
+//! `Recorder` wraps a `StubCalls` implementation that can't rewind on its own
+//! and makes it support GDB's reverse execution commands. It runs the target
+//! one instruction at a time and keeps a journal of the state each step
+//! modified, as well as of all registers and memory modified by the debugger.
+//! `reverse-stepi` and `reverse-continue` then undo the journal entries.
+//!
+//! Continuing steps the target until it reaches a breakpoint inserted by GDB,
+//! a step reports something other than `StopReason::Trap`, or
+//! `StubCalls::step_stopped` says that the program stopped on its own.
+//!
+//! The registers are saved before every step. The memory modified by the
+//! target program itself is best reported by the target (see
+//! `StubCalls::step_writes`). Otherwise, it is only recorded if the target
+//! reports a memory map (see `StubCalls::memory_map`), in which case all RAM
+//! regions are read with `StubCalls::read_mem_range` and compared after each
+//! step. This is slow for large memories, but works with any target. Without
+//! either, reversing only restores the registers and the memory written by
+//! the debugger.
+//!
+//! Like GDB's `record full`, the history is limited to a number of
+//! instructions (see `Recorder::history_limit`), beyond which the oldest
+//! entries are discarded.
+//!
+//! Only the current thread is recorded, so this is meant for single-threaded
+//! targets.
+
+use {Console, Signal, StopReason, StubCalls, Tid};
+use fileio::FileIoReply;
+use hostio::HostIo;
+use library::{Library, SectionOffsets, Svr4LibraryList};
+use memory::{MemoryKind, MemoryRegion};
+use targets::{Registers, TargetDesc};
+
+use std::collections::VecDeque;
+
+/// Default maximum number of recorded instructions, like GDB's default
+/// `record full insn-number-max`.
+const DEFAULT_HISTORY_LIMIT: usize = 200_000;
+
+/// A change to the target state that can be undone.
+enum Entry<R> {
+    /// The debugger wrote the registers, which previously held these values.
+    Registers(R),
+    /// The debugger wrote a byte of memory, which previously held `old`.
+    Memory { addr: u64, old: u8 },
+    /// The target executed an instruction.
+    Step {
+        /// The registers before the step.
+        regs: R,
+        /// The previous contents of the memory modified by the step.
+        mem: Vec<(u64, u8)>,
+    },
+}
+
+/// The contents of the RAM regions before a step, used to find the memory
+/// modified by targets that don't report it.
+struct Snapshot {
+    regions: Vec<MemoryRegion>,
+    contents: Vec<Vec<u8>>,
+}
+
+/// A target whose execution is recorded so that it can be reversed.
+///
+/// Breakpoints are managed by the recorder (using GDB's `Z0` packets) instead
+/// of the wrapped target, since it has to stop at them in both directions.
+pub struct Recorder<T: StubCalls> {
+    target: T,
+    /// Undoable changes, oldest first.
+    journal: VecDeque<Entry<Registers<T>>>,
+    /// Number of `Entry::Step` entries in the journal.
+    steps: usize,
+    /// Maximum number of recorded steps, or 0 for no limit.
+    limit: usize,
+    /// Addresses of the inserted breakpoints.
+    breakpoints: Vec<u64>,
+    /// Whether the memory modified by steps is found by comparing the RAM,
+    /// since the target doesn't report it.
+    compare_memory: bool,
+    /// Whether the missing RAM regions were already reported.
+    warned_no_map: bool,
+}
+
+impl<T: StubCalls> Recorder<T> where Registers<T>: Clone {
+    /// Wraps `target`, starting with an empty execution history.
+    ///
+    /// Unless `target` implements `StubCalls::step_writes`, every recorded
+    /// instruction reads all RAM regions of its memory map and compares them
+    /// with their previous contents, so recording is only practical with
+    /// small memories.
+    pub fn new(target: T) -> Self {
+        Self {
+            target,
+            journal: VecDeque::new(),
+            steps: 0,
+            limit: DEFAULT_HISTORY_LIMIT,
+            breakpoints: Vec::new(),
+            compare_memory: true,
+            warned_no_map: false,
+        }
+    }
+
+    /// Sets the maximum number of instructions kept in the history, after
+    /// which the oldest ones are discarded. 0 means no limit.
+    ///
+    /// The default is 200000 instructions.
+    pub fn history_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self.trim_history();
+        self
+    }
+
+    /// Returns a reference to the wrapped target.
+    ///
+    /// Changes made to the target directly aren't recorded.
+    pub fn target(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    /// Unwraps the target.
+    pub fn into_inner(self) -> T {
+        self.target
+    }
+
+    /// Discards the recorded execution history.
+    pub fn clear_history(&mut self) {
+        self.journal.clear();
+        self.steps = 0;
+    }
+
+    /// Discards the oldest steps exceeding the history limit, along with the
+    /// changes made before them.
+    fn trim_history(&mut self) {
+        while self.limit != 0 && self.steps > self.limit {
+            while let Some(entry) = self.journal.pop_front() {
+                if let Entry::Step { .. } = entry {
+                    self.steps -= 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the program counter of the target.
+    fn pc(&mut self) -> Option<u64> {
+        let regs = self.target.read_registers();
+        T::Target::pc(&regs)
+    }
+
+    /// Executes a single instruction and records its effects.
+    ///
+    /// If the memory has to be compared, `snapshot` holds the RAM contents
+    /// before the step, and is updated for the next one. It is taken here if
+    /// it is `None`.
+    fn record_step(&mut self, snapshot: &mut Option<Snapshot>, console: &mut Console) -> StopReason {
+        let regs = self.target.read_registers();
+        if self.compare_memory && snapshot.is_none() {
+            let regions = self.ram_regions();
+            let contents = self.read_regions(&regions);
+            *snapshot = Some(Snapshot { regions, contents });
+        }
+
+        let reason = self.target.step(console);
+
+        let mem = match self.target.step_writes() {
+            Some(writes) => {
+                self.compare_memory = false;
+                *snapshot = None;
+                writes
+            }
+            None => {
+                self.compare_memory = true;
+                self.compare_snapshot(snapshot)
+            }
+        };
+
+        self.journal.push_back(Entry::Step { regs, mem });
+        self.steps += 1;
+        self.trim_history();
+        reason
+    }
+
+    /// Undoes journal entries up to and including the last step.
+    ///
+    /// Returns `false` if there is no step left to undo.
+    fn undo_step(&mut self) -> bool {
+        while let Some(entry) = self.journal.pop_back() {
+            match entry {
+                Entry::Registers(regs) => self.target.write_registers(regs),
+                Entry::Memory { addr, old } => {
+                    let _ = self.target.write_mem(addr, old);
+                }
+                Entry::Step { regs, mem } => {
+                    for &(addr, old) in mem.iter().rev() {
+                        let _ = self.target.write_mem(addr, old);
+                    }
+                    self.target.write_registers(regs);
+                    self.steps -= 1;
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Returns the previous contents of the memory that changed since
+    /// `snapshot` was taken, and updates it.
+    fn compare_snapshot(&mut self, snapshot: &mut Option<Snapshot>) -> Vec<(u64, u8)> {
+        let snapshot = match *snapshot {
+            Some(ref mut snapshot) => snapshot,
+            // the target stopped reporting its writes, they're lost
+            None => return Vec::new(),
+        };
+        if snapshot.regions.is_empty() && !self.warned_no_map {
+            warn!("target reports neither its memory writes nor RAM regions, memory changes can't be recorded");
+            self.warned_no_map = true;
+        }
+
+        let mut mem = Vec::new();
+        let after = self.read_regions(&snapshot.regions);
+        for (region, (old, new)) in snapshot.regions.iter().zip(snapshot.contents.iter().zip(&after)) {
+            let changed = old.iter().zip(new).enumerate().filter(|&(_, (old, new))| old != new);
+            mem.extend(changed.map(|(offset, (old, _))| (region.start + offset as u64, *old)));
+        }
+        snapshot.contents = after;
+        mem
+    }
+
+    /// Returns the RAM regions whose contents are recorded.
+    fn ram_regions(&mut self) -> Vec<MemoryRegion> {
+        match self.target.memory_map() {
+            Some(regions) => regions.into_iter().filter(|region| region.kind == MemoryKind::Ram).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Reads the contents of `regions`.
+    ///
+    /// Regions that can't be read as a whole are read byte by byte, with
+    /// unreadable bytes read as 0.
+    fn read_regions(&mut self, regions: &[MemoryRegion]) -> Vec<Vec<u8>> {
+        regions.iter()
+            .map(|region| {
+                let mut buf = vec![0; region.length as usize];
+                if self.target.read_mem_range(region.start, &mut buf).is_err() {
+                    for (addr, byte) in (region.start..).zip(&mut buf) {
+                        *byte = self.target.read_mem(addr).unwrap_or(0);
+                    }
+                }
+                buf
+            })
+            .collect()
+    }
+
+    /// Returns whether the target is at an inserted breakpoint.
+    fn at_breakpoint(&mut self) -> bool {
+        match self.pc() {
+            Some(pc) => self.breakpoints.contains(&pc),
+            None => false,
+        }
+    }
+}
+
+impl<T: StubCalls> StubCalls for Recorder<T> where Registers<T>: Clone {
+    type Target = T::Target;
+
+    fn read_registers(&mut self) -> Registers<T> {
+        self.target.read_registers()
+    }
+
+    fn write_registers(&mut self, regs: Registers<T>) {
+        let old = self.target.read_registers();
+        self.journal.push_back(Entry::Registers(old));
+        self.target.write_registers(regs)
+    }
+
+    fn read_mem(&mut self, addr: u64) -> Result<u8, ()> {
+        self.target.read_mem(addr)
+    }
+
+    fn read_mem_range(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), ()> {
+        self.target.read_mem_range(addr, buf)
+    }
+
+    fn write_mem(&mut self, addr: u64, byte: u8) -> Result<(), ()> {
+        let old = self.target.read_mem(addr)?;
+        self.target.write_mem(addr, byte)?;
+        self.journal.push_back(Entry::Memory { addr, old });
+        Ok(())
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64, _kind: u64) -> Option<Result<(), ()>> {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+        Some(Ok(()))
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64, _kind: u64) -> Option<Result<(), ()>> {
+        self.breakpoints.retain(|bp| *bp != addr);
+        Some(Ok(()))
+    }
+
+    fn cont(&mut self, console: &mut Console) -> StopReason {
+        if T::Target::pc(&self.target.read_registers()).is_none() {
+            warn!("target doesn't report its PC, execution can't be recorded");
+            return self.target.cont(console);
+        }
+
+        // the memory only changes by stepping, so the contents after a step
+        // are compared with the next one
+        let mut snapshot = None;
+        loop {
+            match self.record_step(&mut snapshot, console) {
+                StopReason::Trap => {}
+                reason => return reason,
+            }
+
+            if self.target.step_stopped() || self.at_breakpoint() {
+                return StopReason::Trap;
+            }
+        }
+    }
+
+    fn step(&mut self, console: &mut Console) -> StopReason {
+        self.record_step(&mut None, console)
+    }
+
+    fn step_stopped(&mut self) -> bool {
+        self.target.step_stopped()
+    }
+
+    fn can_reverse(&mut self) -> bool {
+        // stepping back to breakpoints requires the PC
+        self.pc().is_some()
+    }
+
+    fn reverse_cont(&mut self, _console: &mut Console) -> StopReason {
+        while self.undo_step() {
+            if self.at_breakpoint() {
+                return StopReason::Trap;
+            }
+        }
+
+        StopReason::HistoryBegin
+    }
+
+    fn reverse_step(&mut self, _console: &mut Console) -> StopReason {
+        if self.undo_step() {
+            StopReason::Trap
+        } else {
+            StopReason::HistoryBegin
+        }
+    }
+
+    fn file_io_reply(&mut self, reply: FileIoReply) {
+        self.target.file_io_reply(reply)
+    }
+
+    fn deliver_signal(&mut self, signal: Signal) {
+        self.target.deliver_signal(signal)
+    }
+
+    fn kill(&mut self) {
+        self.clear_history();
+        self.target.kill()
+    }
+
+    fn restart(&mut self) -> Result<(), ()> {
+        self.clear_history();
+        self.target.restart()
+    }
+
+    fn run(&mut self, filename: Option<&str>, args: &[&str]) -> Result<(), ()> {
+        self.clear_history();
+        self.target.run(filename, args)
+    }
+
+    fn attach(&mut self, pid: u32) -> Result<(), ()> {
+        self.target.attach(pid)
+    }
+
+    fn detach(&mut self) {
+        self.target.detach()
+    }
+
+    fn kill_process(&mut self, pid: u32) {
+        self.target.kill_process(pid)
+    }
+
+    fn detach_process(&mut self, pid: u32) {
+        self.target.detach_process(pid)
+    }
+
+    fn threads(&mut self) -> Vec<Tid> {
+        self.target.threads()
+    }
+
+    fn set_thread(&mut self, thread: Tid) {
+        self.target.set_thread(thread)
+    }
+
+    fn thread_name(&mut self, thread: Tid) -> Option<String> {
+        self.target.thread_name(thread)
+    }
+
+    fn thread_core(&mut self, thread: Tid) -> Option<u32> {
+        self.target.thread_core(thread)
+    }
+
+    fn thread_extra_info(&mut self, thread: Tid) -> Option<String> {
+        self.target.thread_extra_info(thread)
+    }
+
+    fn current_thread(&mut self) -> Tid {
+        self.target.current_thread()
+    }
+
+    fn monitor(&mut self, cmd: &str, console: &mut Console) -> Result<(), ()> {
+        self.target.monitor(cmd, console)
+    }
+
+    fn exec_file(&mut self, pid: Option<u32>) -> Option<String> {
+        self.target.exec_file(pid)
+    }
+
+    fn section_offsets(&mut self) -> Option<SectionOffsets> {
+        self.target.section_offsets()
+    }
+
+    fn libraries(&mut self) -> Option<Vec<Library>> {
+        self.target.libraries()
+    }
+
+    fn libraries_svr4(&mut self) -> Option<Svr4LibraryList> {
+        self.target.libraries_svr4()
+    }
+
+    fn symbols_needed(&mut self) -> Vec<String> {
+        self.target.symbols_needed()
+    }
+
+    fn symbol_resolved(&mut self, name: &str, addr: Option<u64>) {
+        self.target.symbol_resolved(name, addr)
+    }
+
+    fn host_io(&mut self) -> Option<&mut dyn HostIo> {
+        self.target.host_io()
+    }
+
+    fn memory_map(&mut self) -> Option<Vec<MemoryRegion>> {
+        self.target.memory_map()
+    }
+
+    fn flash_erase(&mut self, start: u64, length: u64) -> Result<(), ()> {
+        self.target.flash_erase(start, length)
+    }
+
+    fn flash_write(&mut self, start: u64, data: &[u8]) -> Result<(), ()> {
+        self.target.flash_write(start, data)
+    }
+
+    fn flash_done(&mut self) -> Result<(), ()> {
+        // a new program was loaded, so the history no longer applies
+        self.clear_history();
+        self.target.flash_done()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use targets::{Dynamic, DynamicRegisters, RegDesc};
+    use byteorder::LittleEndian;
+    use std::io::Cursor;
+    use std::mem;
+
+    /// A target whose steps increment the PC and the byte at PC % 8.
+    struct Mock {
+        regs: DynamicRegisters,
+        mem: Vec<u8>,
+        /// Whether `step_writes` reports the writes of the last step.
+        report_writes: bool,
+        writes: Vec<(u64, u8)>,
+        /// Number of bytes read.
+        reads: usize,
+    }
+
+    impl Mock {
+        fn new(report_writes: bool) -> Self {
+            let regs = DynamicRegisters::new(None, "org.gnu.gdb.arm.core", vec![
+                RegDesc::new("r0", 32, "uint32"),
+                RegDesc::new("pc", 32, "code_ptr"),
+            ]);
+            Mock { regs, mem: vec![0; 16], report_writes, writes: Vec::new(), reads: 0 }
+        }
+    }
+
+    impl StubCalls for Mock {
+        type Target = Dynamic<LittleEndian>;
+
+        fn read_registers(&mut self) -> DynamicRegisters { self.regs.clone() }
+        fn write_registers(&mut self, regs: DynamicRegisters) { self.regs = regs; }
+        fn read_mem(&mut self, addr: u64) -> Result<u8, ()> {
+            self.reads += 1;
+            self.mem.get(addr as usize).cloned().ok_or(())
+        }
+        fn write_mem(&mut self, addr: u64, byte: u8) -> Result<(), ()> {
+            *self.mem.get_mut(addr as usize).ok_or(())? = byte;
+            Ok(())
+        }
+        fn cont(&mut self, _console: &mut Console) -> StopReason { unreachable!() }
+        fn step(&mut self, _console: &mut Console) -> StopReason {
+            let pc = self.regs.get(1)[0];
+            let addr = usize::from(pc % 8);
+            self.writes.push((addr as u64, self.mem[addr]));
+            self.mem[addr] += 1;
+            self.regs.get_mut(1)[0] = pc + 1;
+            StopReason::Trap
+        }
+        fn step_writes(&mut self) -> Option<Vec<(u64, u8)>> {
+            if self.report_writes { Some(mem::take(&mut self.writes)) } else { None }
+        }
+        fn memory_map(&mut self) -> Option<Vec<MemoryRegion>> {
+            Some(vec![MemoryRegion::new(0, 16, MemoryKind::Ram)])
+        }
+    }
+
+    fn pc(recorder: &mut Recorder<Mock>) -> u8 {
+        recorder.read_registers().get(1)[0]
+    }
+
+    fn undo(report_writes: bool) {
+        let mut out = Cursor::new(Vec::new());
+        let mut console = Console::new(&mut out);
+        let mut recorder = Recorder::new(Mock::new(report_writes));
+
+        recorder.step(&mut console);
+        recorder.write_mem(8, 0xaa).unwrap();
+        recorder.insert_breakpoint(2, 0);
+        recorder.step(&mut console);
+        recorder.step(&mut console);
+        recorder.step(&mut console);
+        assert_eq!(pc(&mut recorder), 4);
+        assert_eq!(recorder.target().mem[..9], [1, 1, 1, 1, 0, 0, 0, 0, 0xaa]);
+
+        assert!(matches!(recorder.reverse_step(&mut console), StopReason::Trap));
+        assert_eq!(pc(&mut recorder), 3);
+        assert_eq!(recorder.target().mem[..4], [1, 1, 1, 0]);
+
+        // stops at the breakpoint
+        assert!(matches!(recorder.reverse_cont(&mut console), StopReason::Trap));
+        assert_eq!(pc(&mut recorder), 2);
+        assert_eq!(recorder.target().mem[..4], [1, 1, 0, 0]);
+
+        // undoes the debugger's write on the way
+        assert!(matches!(recorder.reverse_cont(&mut console), StopReason::HistoryBegin));
+        assert_eq!(pc(&mut recorder), 0);
+        assert_eq!(recorder.target().mem, [0; 16]);
+        assert!(matches!(recorder.reverse_step(&mut console), StopReason::HistoryBegin));
+    }
+
+    #[test]
+    fn undo_reported_writes() {
+        undo(true);
+    }
+
+    #[test]
+    fn undo_compared_memory() {
+        undo(false);
+    }
+
+    #[test]
+    fn memory_is_only_compared_without_reported_writes() {
+        let mut out = Cursor::new(Vec::new());
+        let mut console = Console::new(&mut out);
+
+        // only the first step reads the memory, before the target is known to
+        // report its writes
+        let mut recorder = Recorder::new(Mock::new(true));
+        for _ in 0..4 {
+            recorder.step(&mut console);
+        }
+        assert_eq!(recorder.target().reads, 16);
+
+        // continuing compares each step with the previous one
+        let mut recorder = Recorder::new(Mock::new(false));
+        recorder.insert_breakpoint(4, 0);
+        assert!(matches!(recorder.cont(&mut console), StopReason::Trap));
+        assert_eq!(recorder.target().reads, 5 * 16);
+    }
+
+    #[test]
+    fn history_limit() {
+        let mut out = Cursor::new(Vec::new());
+        let mut console = Console::new(&mut out);
+        let mut recorder = Recorder::new(Mock::new(true)).history_limit(2);
+        for _ in 0..4 {
+            recorder.step(&mut console);
+        }
+
+        assert!(matches!(recorder.reverse_cont(&mut console), StopReason::HistoryBegin));
+        assert_eq!(pc(&mut recorder), 2);
+        assert_eq!(recorder.target().mem[..4], [1, 1, 0, 0]);
+    }
+}
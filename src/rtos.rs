@@ -0,0 +1,613 @@
+//! RTOS thread awareness.
+//!
+//! Firmware running an RTOS usually executes many tasks on a single core.
+//! `RtosTarget` wraps a `StubCalls` implementation and presents the tasks of
+//! the RTOS to GDB as threads. The task list and the saved registers of tasks
+//! that aren't currently running are read from target memory by an
+//! `RtosAwareness` implementation, which knows the data structures of a
+//! particular RTOS.
+//!
+//! `LinkedListRtos` is a reference implementation for a simple scheduler that
+//! keeps its task control blocks in a linked list.
+
+use {Console, Signal, StopReason, StubCalls, Tid};
+use fileio::FileIoReply;
+use hostio::HostIo;
+use library::{Library, SectionOffsets, Svr4LibraryList};
+use memory::MemoryRegion;
+use targets::{raw_registers, Register, Registers, TargetDesc};
+use utils::read_uint;
+
+/// A task of the RTOS, presented to GDB as a thread.
+#[derive(Debug, Clone)]
+pub struct Task {
+    /// The task ID, used as the thread ID. Must not be 0.
+    pub id: u32,
+    /// The name of the task.
+    pub name: String,
+    /// Whether this task is currently running on the CPU.
+    pub running: bool,
+    /// RTOS-specific handle of the task, eg. the address of its task control
+    /// block.
+    pub handle: u64,
+}
+
+/// Knowledge about the data structures of an RTOS.
+pub trait RtosAwareness<T: StubCalls> {
+    /// Returns the names of the symbols needed to locate the RTOS data
+    /// structures.
+    fn symbols(&self) -> Vec<String>;
+
+    /// Receives the address of a symbol returned by `symbols`, or `None` if
+    /// GDB couldn't find it.
+    fn symbol_resolved(&mut self, name: &str, addr: Option<u64>);
+
+    /// Reads the list of tasks from target memory.
+    ///
+    /// Returns an error if the RTOS isn't running yet (eg. because the
+    /// scheduler hasn't been started, or because the symbols are unknown).
+    fn tasks(&mut self, target: &mut T) -> Result<Vec<Task>, ()>;
+
+    /// Reads the saved registers of a task that isn't currently running.
+    fn read_task_registers(&mut self, target: &mut T, task: &Task) -> Result<Registers<T>, ()>;
+
+    /// Modifies the saved registers of a task that isn't currently running.
+    ///
+    /// This doesn't need to be implemented.
+    fn write_task_registers(&mut self, _target: &mut T, _task: &Task, _regs: Registers<T>) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+/// A target running an RTOS, whose tasks are presented to GDB as threads.
+///
+/// Until the RTOS tasks can be read, the threads of the wrapped target are
+/// reported instead.
+pub struct RtosTarget<T: StubCalls, R: RtosAwareness<T>> {
+    target: T,
+    rtos: R,
+    /// Cached task list, invalidated whenever the target state changes.
+    tasks: Option<Vec<Task>>,
+    /// The task selected by the debugger.
+    selected: Option<u32>,
+}
+
+impl<T: StubCalls, R: RtosAwareness<T>> RtosTarget<T, R> {
+    /// Wraps `target`, using `rtos` to find its tasks.
+    pub fn new(target: T, rtos: R) -> Self {
+        Self {
+            target,
+            rtos,
+            tasks: None,
+            selected: None,
+        }
+    }
+
+    /// Returns a reference to the wrapped target.
+    pub fn target(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    /// Returns the RTOS awareness implementation.
+    pub fn rtos(&mut self) -> &mut R {
+        &mut self.rtos
+    }
+
+    /// Unwraps the target.
+    pub fn into_inner(self) -> T {
+        self.target
+    }
+
+    /// Returns the RTOS tasks, or `None` if they can't be determined.
+    fn tasks(&mut self) -> Option<&[Task]> {
+        if self.tasks.is_none() {
+            self.tasks = self.rtos.tasks(&mut self.target).ok().filter(|tasks| !tasks.is_empty());
+        }
+        self.tasks.as_ref().map(|tasks| &tasks[..])
+    }
+
+    /// Returns the task presented as `thread`, if any.
+    fn task(&mut self, thread: Tid) -> Option<Task> {
+        self.tasks()?.iter().find(|task| task.id == thread.tid).cloned()
+    }
+
+    /// Returns the selected task if it isn't running (ie. its registers are
+    /// saved in memory).
+    fn selected_suspended_task(&mut self) -> Option<Task> {
+        let selected = self.selected?;
+        self.tasks()?.iter().find(|task| task.id == selected && !task.running).cloned()
+    }
+
+    /// Called whenever the target state might change.
+    fn invalidate(&mut self) {
+        self.tasks = None;
+        self.selected = None;
+    }
+
+    fn pid(&mut self) -> u32 {
+        self.target.current_thread().pid
+    }
+}
+
+impl<T: StubCalls, R: RtosAwareness<T>> StubCalls for RtosTarget<T, R> {
+    type Target = T::Target;
+
+    fn read_registers(&mut self) -> Registers<T> {
+        if let Some(task) = self.selected_suspended_task() {
+            match self.rtos.read_task_registers(&mut self.target, &task) {
+                Ok(regs) => return regs,
+                Err(()) => warn!("couldn't read saved registers of task {}", task.id),
+            }
+        }
+
+        self.target.read_registers()
+    }
+
+    fn write_registers(&mut self, regs: Registers<T>) {
+        match self.selected_suspended_task() {
+            Some(task) => {
+                if self.rtos.write_task_registers(&mut self.target, &task, regs).is_err() {
+                    warn!("couldn't write saved registers of task {}", task.id);
+                }
+            }
+            None => self.target.write_registers(regs),
+        }
+        self.tasks = None;
+    }
+
+    fn read_mem(&mut self, addr: u64) -> Result<u8, ()> {
+        self.target.read_mem(addr)
+    }
+
+    fn read_mem_range(&mut self, addr: u64, buf: &mut [u8]) -> Result<(), ()> {
+        self.target.read_mem_range(addr, buf)
+    }
+
+    fn write_mem(&mut self, addr: u64, byte: u8) -> Result<(), ()> {
+        self.tasks = None;
+        self.target.write_mem(addr, byte)
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64, kind: u64) -> Option<Result<(), ()>> {
+        self.target.insert_breakpoint(addr, kind)
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64, kind: u64) -> Option<Result<(), ()>> {
+        self.target.remove_breakpoint(addr, kind)
+    }
+
+    fn cont(&mut self, console: &mut Console) -> StopReason {
+        self.invalidate();
+        self.target.cont(console)
+    }
+
+    fn step(&mut self, console: &mut Console) -> StopReason {
+        // only the running task can be stepped
+        self.invalidate();
+        self.target.step(console)
+    }
+
+    fn step_stopped(&mut self) -> bool {
+        self.target.step_stopped()
+    }
+
+    fn step_writes(&mut self) -> Option<Vec<(u64, u8)>> {
+        self.target.step_writes()
+    }
+
+    fn can_reverse(&mut self) -> bool {
+        self.target.can_reverse()
+    }
+
+    fn reverse_cont(&mut self, console: &mut Console) -> StopReason {
+        self.invalidate();
+        self.target.reverse_cont(console)
+    }
+
+    fn reverse_step(&mut self, console: &mut Console) -> StopReason {
+        self.invalidate();
+        self.target.reverse_step(console)
+    }
+
+    fn file_io_reply(&mut self, reply: FileIoReply) {
+        self.target.file_io_reply(reply)
+    }
+
+    fn deliver_signal(&mut self, signal: Signal) {
+        self.target.deliver_signal(signal)
+    }
+
+    fn kill(&mut self) {
+        self.invalidate();
+        self.target.kill()
+    }
+
+    fn restart(&mut self) -> Result<(), ()> {
+        self.invalidate();
+        self.target.restart()
+    }
+
+    fn run(&mut self, filename: Option<&str>, args: &[&str]) -> Result<(), ()> {
+        self.invalidate();
+        self.target.run(filename, args)
+    }
+
+    fn attach(&mut self, pid: u32) -> Result<(), ()> {
+        self.invalidate();
+        self.target.attach(pid)
+    }
+
+    fn detach(&mut self) {
+        self.target.detach()
+    }
+
+    fn kill_process(&mut self, pid: u32) {
+        self.invalidate();
+        self.target.kill_process(pid)
+    }
+
+    fn detach_process(&mut self, pid: u32) {
+        self.target.detach_process(pid)
+    }
+
+    fn threads(&mut self) -> Vec<Tid> {
+        let pid = self.pid();
+        match self.tasks() {
+            Some(tasks) => tasks.iter().map(|task| Tid::new(pid, task.id)).collect(),
+            None => self.target.threads(),
+        }
+    }
+
+    fn set_thread(&mut self, thread: Tid) {
+        let is_task = self.tasks().map(|tasks| tasks.iter().any(|task| task.id == thread.tid));
+        match is_task {
+            Some(true) => self.selected = Some(thread.tid),
+            _ => {
+                self.selected = None;
+                self.target.set_thread(thread)
+            }
+        }
+    }
+
+    fn current_thread(&mut self) -> Tid {
+        let pid = self.pid();
+        let selected = self.selected;
+        let current = self.tasks().and_then(|tasks| {
+            tasks.iter()
+                .find(|task| Some(task.id) == selected)
+                .or_else(|| tasks.iter().find(|task| task.running))
+                .map(|task| Tid::new(pid, task.id))
+        });
+
+        match current {
+            Some(current) => current,
+            None => self.target.current_thread(),
+        }
+    }
+
+    fn thread_name(&mut self, thread: Tid) -> Option<String> {
+        match self.task(thread) {
+            Some(task) => Some(task.name),
+            None => self.target.thread_name(thread),
+        }
+    }
+
+    fn thread_core(&mut self, thread: Tid) -> Option<u32> {
+        match self.task(thread) {
+            // all tasks share the core of the wrapped target's thread
+            Some(_) => {
+                let current = self.target.current_thread();
+                self.target.thread_core(current)
+            }
+            None => self.target.thread_core(thread),
+        }
+    }
+
+    fn thread_extra_info(&mut self, thread: Tid) -> Option<String> {
+        match self.task(thread) {
+            Some(task) => Some(if task.running { "Running" } else { "Suspended" }.to_string()),
+            None => self.target.thread_extra_info(thread),
+        }
+    }
+
+    fn monitor(&mut self, cmd: &str, console: &mut Console) -> Result<(), ()> {
+        self.invalidate();
+        self.target.monitor(cmd, console)
+    }
+
+    fn exec_file(&mut self, pid: Option<u32>) -> Option<String> {
+        self.target.exec_file(pid)
+    }
+
+    fn section_offsets(&mut self) -> Option<SectionOffsets> {
+        self.target.section_offsets()
+    }
+
+    fn libraries(&mut self) -> Option<Vec<Library>> {
+        self.target.libraries()
+    }
+
+    fn libraries_svr4(&mut self) -> Option<Svr4LibraryList> {
+        self.target.libraries_svr4()
+    }
+
+    fn symbols_needed(&mut self) -> Vec<String> {
+        let mut symbols = self.rtos.symbols();
+        symbols.extend(self.target.symbols_needed());
+        symbols
+    }
+
+    fn symbol_resolved(&mut self, name: &str, addr: Option<u64>) {
+        if self.rtos.symbols().iter().any(|symbol| symbol == name) {
+            self.rtos.symbol_resolved(name, addr);
+            self.tasks = None;
+        } else {
+            self.target.symbol_resolved(name, addr);
+        }
+    }
+
+    fn host_io(&mut self) -> Option<&mut dyn HostIo> {
+        self.target.host_io()
+    }
+
+    fn memory_map(&mut self) -> Option<Vec<MemoryRegion>> {
+        self.target.memory_map()
+    }
+
+    fn flash_erase(&mut self, start: u64, length: u64) -> Result<(), ()> {
+        self.target.flash_erase(start, length)
+    }
+
+    fn flash_write(&mut self, start: u64, data: &[u8]) -> Result<(), ()> {
+        self.target.flash_write(start, data)
+    }
+
+    fn flash_done(&mut self) -> Result<(), ()> {
+        self.tasks = None;
+        self.target.flash_done()
+    }
+}
+
+/// Layout of the task control blocks (TCBs) used by `LinkedListRtos`.
+///
+/// All offsets are relative to the start of a TCB. Pointers and integers are
+/// stored in target byte order.
+#[derive(Debug, Clone)]
+pub struct TcbLayout {
+    /// Name of the pointer variable holding the address of the first TCB.
+    pub list_symbol: String,
+    /// Name of the pointer variable holding the address of the TCB of the
+    /// running task.
+    pub current_symbol: String,
+    /// Size of a pointer in bytes.
+    pub pointer_size: usize,
+    /// Offset of the pointer to the next TCB. A null pointer ends the list.
+    pub next: u64,
+    /// Offset of the 32-bit task ID.
+    pub id: u64,
+    /// Offset of the NUL-terminated task name.
+    pub name: u64,
+    /// Maximum length of the task name in bytes.
+    pub name_len: usize,
+    /// Offset of the saved registers of a suspended task.
+    ///
+    /// The registers are saved in the same format they're sent to GDB in.
+    pub regs: u64,
+}
+
+impl Default for TcbLayout {
+    /// The layout of a 32-bit TCB:
+    ///
+    /// ```c
+    /// struct tcb {
+    ///     struct tcb *next;
+    ///     uint32_t id;
+    ///     char name[16];
+    ///     /* saved registers */
+    /// };
+    /// extern struct tcb *task_list, *current_task;
+    /// ```
+    fn default() -> Self {
+        TcbLayout {
+            list_symbol: "task_list".to_string(),
+            current_symbol: "current_task".to_string(),
+            pointer_size: 4,
+            next: 0,
+            id: 4,
+            name: 8,
+            name_len: 16,
+            regs: 24,
+        }
+    }
+}
+
+/// RTOS awareness for a simple scheduler keeping its tasks in a linked list
+/// of task control blocks (see `TcbLayout`).
+#[derive(Debug, Clone)]
+pub struct LinkedListRtos {
+    layout: TcbLayout,
+    list_addr: Option<u64>,
+    current_addr: Option<u64>,
+}
+
+impl LinkedListRtos {
+    /// Maximum number of tasks read from the list, in case it's corrupted.
+    const MAX_TASKS: usize = 1024;
+
+    /// Creates an RTOS awareness implementation for TCBs with the given
+    /// layout.
+    pub fn new(layout: TcbLayout) -> Self {
+        Self {
+            layout,
+            list_addr: None,
+            current_addr: None,
+        }
+    }
+
+    fn read_name<T: StubCalls>(&self, target: &mut T, tcb: u64) -> Result<String, ()> {
+        let addr = tcb.checked_add(self.layout.name).ok_or(())?;
+        let mut name = Vec::new();
+        for i in 0..self.layout.name_len as u64 {
+            match target.read_mem(addr.checked_add(i).ok_or(())?)? {
+                0 => break,
+                byte => name.push(byte),
+            }
+        }
+        Ok(String::from_utf8_lossy(&name).into_owned())
+    }
+}
+
+impl<T: StubCalls> RtosAwareness<T> for LinkedListRtos {
+    fn symbols(&self) -> Vec<String> {
+        vec![self.layout.list_symbol.clone(), self.layout.current_symbol.clone()]
+    }
+
+    fn symbol_resolved(&mut self, name: &str, addr: Option<u64>) {
+        if name == self.layout.list_symbol {
+            self.list_addr = addr;
+        } else if name == self.layout.current_symbol {
+            self.current_addr = addr;
+        }
+    }
+
+    fn tasks(&mut self, target: &mut T) -> Result<Vec<Task>, ()> {
+        let (list_addr, current_addr) = match (self.list_addr, self.current_addr) {
+            (Some(list), Some(current)) => (list, current),
+            _ => return Err(()),
+        };
+
+        let pointer_size = self.layout.pointer_size;
+        let current = read_uint(target, current_addr, pointer_size)?;
+        let mut tcb = read_uint(target, list_addr, pointer_size)?;
+        let mut tasks = Vec::new();
+        while tcb != 0 && tasks.len() < Self::MAX_TASKS {
+            let id = read_uint(target, tcb.checked_add(self.layout.id).ok_or(())?, 4)? as u32;
+            if id == 0 {
+                warn!("task at {:#x} has invalid ID 0", tcb);
+            } else {
+                tasks.push(Task {
+                    id,
+                    name: self.read_name(target, tcb)?,
+                    running: tcb == current,
+                    handle: tcb,
+                });
+            }
+
+            tcb = read_uint(target, tcb.checked_add(self.layout.next).ok_or(())?, pointer_size)?;
+        }
+
+        Ok(tasks)
+    }
+
+    fn read_task_registers(&mut self, target: &mut T, task: &Task) -> Result<Registers<T>, ()> {
+        // decode into the live registers, since their layout might only be
+        // known at runtime
+        let mut regs = target.read_registers();
+        let addr = task.handle.checked_add(self.layout.regs).ok_or(())?;
+        let mut raw = vec![0; raw_registers::<T::Target>(&regs).len()];
+        for (i, byte) in raw.iter_mut().enumerate() {
+            *byte = target.read_mem(addr.checked_add(i as u64).ok_or(())?)?;
+        }
+
+        regs.decode_in_place::<_, <T::Target as TargetDesc>::Endianness>(&mut &raw[..]).map_err(|_| ())?;
+        Ok(regs)
+    }
+
+    fn write_task_registers(&mut self, target: &mut T, task: &Task, regs: Registers<T>) -> Result<(), ()> {
+        let addr = task.handle.checked_add(self.layout.regs).ok_or(())?;
+        let raw = raw_registers::<T::Target>(&regs);
+
+        for (i, byte) in raw.iter().enumerate() {
+            target.write_mem(addr.checked_add(i as u64).ok_or(())?, *byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use targets::{Dynamic, DynamicRegisters, RegDesc};
+    use byteorder::LittleEndian;
+
+    struct Mock {
+        regs: DynamicRegisters,
+        mem: Vec<u8>,
+    }
+
+    impl Mock {
+        /// Creates a target with the TCBs of two tasks, `idle` and the running
+        /// `main`, in the default layout.
+        fn new() -> Self {
+            let regs = DynamicRegisters::new(None, "org.gnu.gdb.arm.core", vec![
+                RegDesc::new("r0", 32, "uint32"),
+                RegDesc::new("pc", 32, "code_ptr"),
+            ]);
+            let mut mock = Mock { regs, mem: vec![0; 0x100] };
+            // task_list, current_task
+            mock.store(0x00, &[0x40, 0, 0, 0]);
+            mock.store(0x04, &[0x80, 0, 0, 0]);
+            // idle
+            mock.store(0x40, &[0x80, 0, 0, 0, 1, 0, 0, 0]);
+            mock.store(0x48, b"idle\0");
+            mock.store(0x58, &[1, 2, 3, 4, 5, 6, 7, 8]);
+            // main
+            mock.store(0x84, &[2, 0, 0, 0]);
+            mock.store(0x88, b"main\0");
+            mock
+        }
+
+        fn store(&mut self, addr: usize, bytes: &[u8]) {
+            self.mem[addr..addr + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    impl StubCalls for Mock {
+        type Target = Dynamic<LittleEndian>;
+
+        fn read_registers(&mut self) -> DynamicRegisters { self.regs.clone() }
+        fn write_registers(&mut self, regs: DynamicRegisters) { self.regs = regs; }
+        fn read_mem(&mut self, addr: u64) -> Result<u8, ()> { self.mem.get(addr as usize).cloned().ok_or(()) }
+        fn write_mem(&mut self, addr: u64, byte: u8) -> Result<(), ()> {
+            *self.mem.get_mut(addr as usize).ok_or(())? = byte;
+            Ok(())
+        }
+        fn cont(&mut self, _console: &mut Console) -> StopReason { unreachable!() }
+        fn step(&mut self, _console: &mut Console) -> StopReason { unreachable!() }
+    }
+
+    fn rtos(layout: TcbLayout) -> LinkedListRtos {
+        let mut rtos = LinkedListRtos::new(layout);
+        RtosAwareness::<Mock>::symbol_resolved(&mut rtos, "task_list", Some(0x00));
+        RtosAwareness::<Mock>::symbol_resolved(&mut rtos, "current_task", Some(0x04));
+        rtos
+    }
+
+    #[test]
+    fn linked_list_tasks() {
+        let mut target = Mock::new();
+        let mut rtos = rtos(TcbLayout::default());
+        let tasks = rtos.tasks(&mut target).unwrap();
+        let tasks: Vec<_> = tasks.iter().map(|task| (task.id, &*task.name, task.running, task.handle)).collect();
+        assert_eq!(tasks, [(1, "idle", false, 0x40), (2, "main", true, 0x80)]);
+
+        // the saved registers follow the name
+        let idle = rtos.tasks(&mut target).unwrap().remove(0);
+        let regs = rtos.read_task_registers(&mut target, &idle).unwrap();
+        assert_eq!(raw_registers::<Dynamic<LittleEndian>>(&regs), [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn linked_list_errors() {
+        // the symbols weren't looked up yet
+        let mut target = Mock::new();
+        assert!(RtosAwareness::<Mock>::tasks(&mut LinkedListRtos::new(TcbLayout::default()), &mut target).is_err());
+
+        // a corrupted next pointer at the end of the address space
+        target.store(0x40, &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        let layout = TcbLayout { pointer_size: 8, id: 8, name: 12, regs: 28, ..TcbLayout::default() };
+        target.store(0x48, &[1, 0, 0, 0]);
+        target.store(0x00, &[0x40, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(rtos(layout).tasks(&mut target).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+
+/// A signal number as used by the GDB remote protocol.
+///
+/// GDB uses its own target-independent numbering, which matches the
+/// traditional Unix numbering for the common signals. Targets without Unix
+/// signals can use them to model exceptions or interrupts, for example
+/// `SIGSEGV` for a memory fault or `SIGILL` for an undefined instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Signal(pub u8);
+
+impl Signal {
+    /// No signal.
+    pub const NONE: Signal = Signal(0);
+    pub const SIGHUP: Signal = Signal(1);
+    pub const SIGINT: Signal = Signal(2);
+    pub const SIGQUIT: Signal = Signal(3);
+    pub const SIGILL: Signal = Signal(4);
+    pub const SIGTRAP: Signal = Signal(5);
+    pub const SIGABRT: Signal = Signal(6);
+    pub const SIGEMT: Signal = Signal(7);
+    pub const SIGFPE: Signal = Signal(8);
+    pub const SIGKILL: Signal = Signal(9);
+    pub const SIGBUS: Signal = Signal(10);
+    pub const SIGSEGV: Signal = Signal(11);
+    pub const SIGSYS: Signal = Signal(12);
+    pub const SIGPIPE: Signal = Signal(13);
+    pub const SIGALRM: Signal = Signal(14);
+    pub const SIGTERM: Signal = Signal(15);
+    pub const SIGURG: Signal = Signal(16);
+    pub const SIGSTOP: Signal = Signal(17);
+    pub const SIGTSTP: Signal = Signal(18);
+    pub const SIGCONT: Signal = Signal(19);
+    pub const SIGCHLD: Signal = Signal(20);
+    pub const SIGUSR1: Signal = Signal(30);
+    pub const SIGUSR2: Signal = Signal(31);
+
+    /// Returns the name of the signal, if it is one of the named constants.
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self.0 {
+            0 => "none",
+            1 => "SIGHUP",
+            2 => "SIGINT",
+            3 => "SIGQUIT",
+            4 => "SIGILL",
+            5 => "SIGTRAP",
+            6 => "SIGABRT",
+            7 => "SIGEMT",
+            8 => "SIGFPE",
+            9 => "SIGKILL",
+            10 => "SIGBUS",
+            11 => "SIGSEGV",
+            12 => "SIGSYS",
+            13 => "SIGPIPE",
+            14 => "SIGALRM",
+            15 => "SIGTERM",
+            16 => "SIGURG",
+            17 => "SIGSTOP",
+            18 => "SIGTSTP",
+            19 => "SIGCONT",
+            20 => "SIGCHLD",
+            30 => "SIGUSR1",
+            31 => "SIGUSR2",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
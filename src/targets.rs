@@ -1,9 +1,13 @@
 //! Target platform definitions.
 
-use Comm;
+use {Comm, StubCalls};
+use utils::{hex_decode_in_place, xml_escape};
 
 use byteorder::{ByteOrder, ReadBytesExt};
+use std::borrow::Cow;
+use std::fmt::Write;
 use std::io::{self, Read};
+use std::marker::PhantomData;
 
 macro_rules! def_regs {
     (
@@ -31,6 +35,12 @@ macro_rules! def_regs {
                     $( $reg: <$t as ::targets::Register>::decode::<R, B>(read)?, )+
                 })
             }
+
+            fn describe(&self, _name: &str, regs: &mut Vec<::targets::RegDesc>) {
+                $(
+                    self.$reg.describe(stringify!($reg), regs);
+                )+
+            }
         }
     };
 }
@@ -44,6 +54,202 @@ pub trait TargetDesc {
 
     /// The target endianness.
     type Endianness: ByteOrder;
+
+    /// Returns the architecture name put into the target description XML (eg.
+    /// `i386` or `arm`).
+    ///
+    /// If this returns `None`, GDB will use the architecture of the loaded
+    /// executable.
+    fn architecture(_regs: &Self::Registers) -> Option<&str> {
+        None
+    }
+
+    /// Returns the name of the target description feature containing the
+    /// registers.
+    ///
+    /// GDB uses well-known feature names (eg. `org.gnu.gdb.i386.core`) to
+    /// identify the registers it expects for an architecture.
+    fn feature(_regs: &Self::Registers) -> &str {
+        "org.gnu.gdb.gdbstub.regs"
+    }
+
+    /// Returns the descriptors of the registers in `regs`, in the order they
+    /// are encoded in.
+    ///
+    /// By default, this collects the descriptors from `Register::describe`.
+    /// Targets can override it to adjust the types or features of individual
+    /// registers.
+    fn describe(regs: &Self::Registers) -> Vec<RegDesc> {
+        let mut descs = Vec::new();
+        regs.describe("", &mut descs);
+        descs
+    }
+
+    /// Returns the value of the program counter.
+    ///
+    /// This is used by features that inspect the PC in the stub, such as
+    /// resuming at a different address. If this returns `None`, those
+    /// features are unavailable.
+    fn pc(_regs: &Self::Registers) -> Option<u64> {
+        None
+    }
+
+    /// Sets the program counter to `pc`.
+    ///
+    /// Returns an error if the PC can't be modified.
+    fn set_pc(_regs: &mut Self::Registers, _pc: u64) -> Result<(), ()> {
+        Err(())
+    }
+}
+
+/// The registers of the target `T`.
+pub type Registers<T> = <<T as StubCalls>::Target as TargetDesc>::Registers;
+
+/// Describes a single register in the target description sent to GDB.
+#[derive(Debug, Clone)]
+pub struct RegDesc {
+    /// The register name as displayed by GDB (eg. `eax`).
+    pub name: Cow<'static, str>,
+    /// Size of the register in bits. Must be a multiple of 8.
+    pub bitsize: u32,
+    /// The GDB type of the register (eg. `uint32`, `code_ptr` or `i387_ext`).
+    pub ty: Cow<'static, str>,
+    /// The target description feature containing the register, or `None` for
+    /// the feature returned by `TargetDesc::feature`.
+    pub feature: Option<Cow<'static, str>>,
+}
+
+impl RegDesc {
+    /// Creates a register descriptor.
+    pub fn new<N, T>(name: N, bitsize: u32, ty: T) -> Self
+        where N: Into<Cow<'static, str>>, T: Into<Cow<'static, str>> {
+        Self {
+            name: name.into(),
+            bitsize,
+            ty: ty.into(),
+            feature: None,
+        }
+    }
+
+    /// Puts the register into the target description feature `feature`.
+    pub fn with_feature<F: Into<Cow<'static, str>>>(mut self, feature: F) -> Self {
+        self.feature = Some(feature.into());
+        self
+    }
+}
+
+/// Definitions of the non-builtin register types GDB expects for some
+/// well-known features, which have to be included in the XML.
+const TYPE_DEFS: &[(&str, &str)] = &[
+    ("vec128", concat!(
+        r#"<vector id="v4f" type="ieee_single" count="4"/>"#,
+        r#"<vector id="v2d" type="ieee_double" count="2"/>"#,
+        r#"<vector id="v16i8" type="int8" count="16"/>"#,
+        r#"<vector id="v8i16" type="int16" count="8"/>"#,
+        r#"<vector id="v4i32" type="int32" count="4"/>"#,
+        r#"<vector id="v2i64" type="int64" count="2"/>"#,
+        r#"<union id="vec128">"#,
+        r#"<field name="v4_float" type="v4f"/>"#,
+        r#"<field name="v2_double" type="v2d"/>"#,
+        r#"<field name="v16_int8" type="v16i8"/>"#,
+        r#"<field name="v8_int16" type="v8i16"/>"#,
+        r#"<field name="v4_int32" type="v4i32"/>"#,
+        r#"<field name="v2_int64" type="v2i64"/>"#,
+        r#"<field name="uint128" type="uint128"/>"#,
+        r#"</union>"#,
+    )),
+    ("i386_mxcsr", concat!(
+        r#"<flags id="i386_mxcsr" size="4">"#,
+        r#"<field name="IE" start="0" end="0"/>"#,
+        r#"<field name="DE" start="1" end="1"/>"#,
+        r#"<field name="ZE" start="2" end="2"/>"#,
+        r#"<field name="OE" start="3" end="3"/>"#,
+        r#"<field name="UE" start="4" end="4"/>"#,
+        r#"<field name="PE" start="5" end="5"/>"#,
+        r#"<field name="DAZ" start="6" end="6"/>"#,
+        r#"<field name="IM" start="7" end="7"/>"#,
+        r#"<field name="DM" start="8" end="8"/>"#,
+        r#"<field name="ZM" start="9" end="9"/>"#,
+        r#"<field name="OM" start="10" end="10"/>"#,
+        r#"<field name="UM" start="11" end="11"/>"#,
+        r#"<field name="PM" start="12" end="12"/>"#,
+        r#"<field name="FZ" start="15" end="15"/>"#,
+        r#"</flags>"#,
+    )),
+];
+
+/// Builds the target description XML (`target.xml`) for the given registers.
+///
+/// Registers are grouped into features in the order they are described in.
+/// Returns `None` if the descriptors don't cover all of the encoded register
+/// contents (eg. because `Register::describe` isn't implemented), in which case
+/// GDB has to use its built-in register layout.
+pub(crate) fn target_xml<T: TargetDesc>(regs: &T::Registers) -> Option<String> {
+    let descs = T::describe(regs);
+    let described: u64 = descs.iter().map(|desc| u64::from(desc.bitsize)).sum();
+    if descs.is_empty() || described != raw_registers::<T>(regs).len() as u64 * 8 {
+        return None;
+    }
+
+    let mut xml = String::from(r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0">"#);
+    if let Some(arch) = T::architecture(regs) {
+        write!(xml, "<architecture>{}</architecture>", xml_escape(arch)).unwrap();
+    }
+
+    let mut current: Option<&str> = None;
+    for (i, desc) in descs.iter().enumerate() {
+        let feature = desc.feature.as_deref().unwrap_or_else(|| T::feature(regs));
+        if current != Some(feature) {
+            if current.is_some() {
+                xml.push_str("</feature>");
+            }
+            write!(xml, r#"<feature name="{}">"#, xml_escape(feature)).unwrap();
+            current = Some(feature);
+
+            // types have to be defined in the feature using them
+            let in_feature = descs[i..].iter()
+                .take_while(|desc| desc.feature.as_deref().unwrap_or_else(|| T::feature(regs)) == feature);
+            let mut defined = Vec::new();
+            for desc in in_feature {
+                if let Some(&(ty, def)) = TYPE_DEFS.iter().find(|&&(ty, _)| ty == desc.ty) {
+                    if !defined.contains(&ty) {
+                        xml.push_str(def);
+                        defined.push(ty);
+                    }
+                }
+            }
+        }
+        write!(xml, r#"<reg name="{}" bitsize="{}" type="{}"/>"#,
+               xml_escape(&desc.name), desc.bitsize, xml_escape(&desc.ty)).unwrap();
+    }
+    xml.push_str("</feature></target>");
+    Some(xml)
+}
+
+/// Returns the raw contents of `regs` in target byte order, as they are sent
+/// to GDB in a `g` reply before hex encoding.
+pub(crate) fn raw_registers<T: TargetDesc>(regs: &T::Registers) -> Vec<u8> {
+    let mut hex = HexCollector(Vec::new());
+    regs.encode::<_, T::Endianness>(&mut hex).expect("collecting registers failed");
+    let len = hex_decode_in_place(&mut hex.0).expect("registers encoded invalid hex").len();
+    hex.0.truncate(len);
+    hex.0
+}
+
+/// A `Comm` collecting the hex-encoded register contents.
+struct HexCollector(Vec<u8>);
+
+impl Comm for HexCollector {
+    type Error = io::Error;
+
+    fn read(&mut self) -> Result<u8, io::Error> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "cannot read from HexCollector"))
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), io::Error> {
+        self.0.push(byte);
+        Ok(())
+    }
 }
 
 /// Trait for registers and structs of registers.
@@ -65,6 +271,27 @@ pub trait Register: Sized {
     /// `B` specifies the endianness to use and is set to the target's native
     /// endianness by the library.
     fn decode<R: Read, B: ByteOrder>(reader: &mut R) -> Result<Self, io::Error>;
+
+    /// Decode new register value(s) from raw bytes into `self`.
+    ///
+    /// This is used when the layout of the registers is only known at runtime
+    /// (see `DynamicRegisters`). By default, it replaces `self` with the result
+    /// of `decode`.
+    fn decode_in_place<R: Read, B: ByteOrder>(&mut self, reader: &mut R) -> Result<(), io::Error> {
+        *self = Self::decode::<R, B>(reader)?;
+        Ok(())
+    }
+
+    /// Append descriptors of the register(s) in `self` to `regs`.
+    ///
+    /// `name` is the name of the register, if it is part of a register struct
+    /// (it is empty otherwise). This is used to build the target description
+    /// XML sent to GDB.
+    ///
+    /// By default, nothing is described. Without descriptors for all
+    /// registers, no target description is sent and GDB uses its built-in
+    /// register layout for the architecture.
+    fn describe(&self, _name: &str, _regs: &mut Vec<RegDesc>) {}
 }
 
 impl Register for u32 {
@@ -75,7 +302,10 @@ impl Register for u32 {
     }
 
     fn decode<R: Read, B: ByteOrder>(reader: &mut R) -> Result<Self, io::Error> {
-        Ok(reader.read_u32::<B>()?)
+        reader.read_u32::<B>()
+    }
+    fn describe(&self, name: &str, regs: &mut Vec<RegDesc>) {
+        regs.push(RegDesc::new(name.to_string(), 32, "uint32"));
     }
 }
 
@@ -87,7 +317,10 @@ impl Register for u64 {
     }
 
     fn decode<R: Read, B: ByteOrder>(reader: &mut R) -> Result<Self, io::Error> {
-        Ok(reader.read_u64::<B>()?)
+        reader.read_u64::<B>()
+    }
+    fn describe(&self, name: &str, regs: &mut Vec<RegDesc>) {
+        regs.push(RegDesc::new(name.to_string(), 64, "uint64"));
     }
 }
 
@@ -99,7 +332,10 @@ impl Register for u128 {
     }
 
     fn decode<R: Read, B: ByteOrder>(reader: &mut R) -> Result<Self, io::Error> {
-        Ok(reader.read_u128::<B>()?)
+        reader.read_u128::<B>()
+    }
+    fn describe(&self, name: &str, regs: &mut Vec<RegDesc>) {
+        regs.push(RegDesc::new(name.to_string(), 128, "uint128"));
     }
 }
 
@@ -114,6 +350,9 @@ impl Register for [u8; 10] {
         reader.read_exact(&mut buf)?;
         Ok(buf)
     }
+    fn describe(&self, name: &str, regs: &mut Vec<RegDesc>) {
+        regs.push(RegDesc::new(name.to_string(), 80, "i387_ext"));
+    }
 }
 
 /// Does nothing.
@@ -125,6 +364,149 @@ impl Register for () {
     fn decode<R: Read, B: ByteOrder>(_reader: &mut R) -> Result<Self, io::Error> {
         Ok(())
     }
+
+    fn describe(&self, _name: &str, _regs: &mut Vec<RegDesc>) {}
+}
+
+/// A register set whose layout is only known at runtime.
+///
+/// This consists of a list of register descriptors and a byte buffer holding
+/// the register contents in target byte order, in the order they are
+/// described. Use this together with the `Dynamic` target descriptor when the
+/// emulated CPU is selected at runtime.
+#[derive(Debug, Clone)]
+pub struct DynamicRegisters {
+    architecture: Option<Cow<'static, str>>,
+    feature: Cow<'static, str>,
+    descs: Vec<RegDesc>,
+    offsets: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl DynamicRegisters {
+    /// Creates a zero-initialized register set with the given layout.
+    ///
+    /// `feature` is the name of the target description feature the registers
+    /// belong to (eg. `org.gnu.gdb.arm.core`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of any register is not a multiple of 8 bits.
+    pub fn new<F>(architecture: Option<Cow<'static, str>>, feature: F, descs: Vec<RegDesc>) -> Self
+        where F: Into<Cow<'static, str>> {
+        let mut offsets = Vec::with_capacity(descs.len());
+        let mut size = 0;
+        for desc in &descs {
+            assert!(desc.bitsize % 8 == 0, "size of register '{}' is not a multiple of 8 bits", desc.name);
+            offsets.push(size);
+            size += desc.bitsize as usize / 8;
+        }
+
+        Self {
+            architecture,
+            feature: feature.into(),
+            descs,
+            offsets,
+            data: vec![0; size],
+        }
+    }
+
+    /// Returns the register descriptors.
+    pub fn descs(&self) -> &[RegDesc] {
+        &self.descs
+    }
+
+    /// Returns the index of the register called `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.descs.iter().position(|desc| desc.name == name)
+    }
+
+    /// Returns the index of the program counter.
+    ///
+    /// This is the register called `pc`, `rip` or `eip`, in that order.
+    pub fn pc_index(&self) -> Option<usize> {
+        ["pc", "rip", "eip"].iter().filter_map(|name| self.index_of(name)).next()
+    }
+
+    /// Returns the raw contents of register `index` in target byte order.
+    pub fn get(&self, index: usize) -> &[u8] {
+        let start = self.offsets[index];
+        &self.data[start..start + self.descs[index].bitsize as usize / 8]
+    }
+
+    /// Returns the raw contents of register `index` in target byte order for
+    /// modification.
+    pub fn get_mut(&mut self, index: usize) -> &mut [u8] {
+        let start = self.offsets[index];
+        &mut self.data[start..start + self.descs[index].bitsize as usize / 8]
+    }
+
+    /// Returns the raw contents of all registers.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the raw contents of all registers for modification.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Register for DynamicRegisters {
+    fn encode<C: Comm, B: ByteOrder>(&self, comm: &mut C) -> Result<(), C::Error> {
+        comm.write_all_hex(&self.data)
+    }
+
+    /// Decodes all remaining data as a single unnamed blob.
+    ///
+    /// Since the layout isn't known here, `decode_in_place` should be used
+    /// instead.
+    fn decode<R: Read, B: ByteOrder>(reader: &mut R) -> Result<Self, io::Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut regs = Self::new(None, "org.gnu.gdb.gdbstub.regs", Vec::new());
+        regs.data = data;
+        Ok(regs)
+    }
+
+    fn decode_in_place<R: Read, B: ByteOrder>(&mut self, reader: &mut R) -> Result<(), io::Error> {
+        reader.read_exact(&mut self.data)
+    }
+
+    fn describe(&self, _name: &str, regs: &mut Vec<RegDesc>) {
+        regs.extend(self.descs.iter().cloned());
+    }
+}
+
+/// A target whose registers are defined at runtime.
+///
+/// `B` is the target endianness.
+pub struct Dynamic<B: ByteOrder>(PhantomData<B>);
+
+impl<B: ByteOrder> TargetDesc for Dynamic<B> {
+    type Registers = DynamicRegisters;
+    type Endianness = B;
+
+    fn architecture(regs: &DynamicRegisters) -> Option<&str> {
+        regs.architecture.as_deref()
+    }
+
+    fn feature(regs: &DynamicRegisters) -> &str {
+        &regs.feature
+    }
+
+    fn pc(regs: &DynamicRegisters) -> Option<u64> {
+        let pc = regs.get(regs.pc_index()?);
+        Some(B::read_uint(pc, pc.len()))
+    }
+
+    fn set_pc(regs: &mut DynamicRegisters, pc: u64) -> Result<(), ()> {
+        let index = regs.pc_index().ok_or(())?;
+        let reg = regs.get_mut(index);
+        let len = reg.len();
+        B::write_uint(reg, pc, len);
+        Ok(())
+    }
 }
 
 /// The Intel x86 family of processors.
@@ -135,6 +517,43 @@ pub mod x86 {
     impl super::TargetDesc for I386 {
         type Registers = X86Registers;
         type Endianness = ::byteorder::LittleEndian;
+
+        fn architecture(_regs: &X86Registers) -> Option<&str> {
+            Some("i386")
+        }
+
+        fn feature(_regs: &X86Registers) -> &str {
+            "org.gnu.gdb.i386.core"
+        }
+
+        fn describe(regs: &X86Registers) -> Vec<super::RegDesc> {
+            let mut descs = Vec::new();
+            super::Register::describe(regs, "", &mut descs);
+            for desc in &mut descs {
+                match &*desc.name {
+                    "eip" => desc.ty = "code_ptr".into(),
+                    "mxcsr" => {
+                        desc.ty = "i386_mxcsr".into();
+                        desc.feature = Some("org.gnu.gdb.i386.sse".into());
+                    }
+                    name if name.starts_with("xmm") => {
+                        desc.ty = "vec128".into();
+                        desc.feature = Some("org.gnu.gdb.i386.sse".into());
+                    }
+                    _ => {}
+                }
+            }
+            descs
+        }
+
+        fn pc(regs: &X86Registers) -> Option<u64> {
+            Some(regs.eip.into())
+        }
+
+        fn set_pc(regs: &mut X86Registers, pc: u64) -> Result<(), ()> {
+            regs.eip = pc as u32;
+            Ok(())
+        }
     }
 
     def_regs! {
@@ -192,3 +611,65 @@ pub mod x86 {
     }
     // FIXME how to handle extensions like MMX/SSE/...?
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::LittleEndian;
+
+    #[test]
+    fn x86_target_xml() {
+        let regs = x86::X86Registers::decode::<_, LittleEndian>(&mut &[0; 512][..]).unwrap();
+        let xml = target_xml::<x86::I386>(&regs).unwrap();
+
+        assert!(xml.contains(concat!(
+            r#"<architecture>i386</architecture><feature name="org.gnu.gdb.i386.core">"#,
+            r#"<reg name="eax" bitsize="32" type="uint32"/>"#,
+        )));
+        assert!(xml.contains(r#"<reg name="eip" bitsize="32" type="code_ptr"/>"#));
+        assert!(xml.contains(r#"<reg name="st0" bitsize="80" type="i387_ext"/>"#));
+        assert_eq!(xml.matches("<feature ").count(), 2);
+
+        // the SSE registers are in their own feature, which defines their types
+        let sse = &xml[xml.find(r#"<feature name="org.gnu.gdb.i386.sse">"#).unwrap()..];
+        assert!(sse.find(r#"<union id="vec128">"#).unwrap() < sse.find(r#"<reg name="xmm0""#).unwrap());
+        assert!(sse.find(r#"<flags id="i386_mxcsr" size="4">"#).unwrap() < sse.find(r#"<reg name="mxcsr""#).unwrap());
+        assert!(sse.contains(r#"<reg name="xmm7" bitsize="128" type="vec128"/>"#));
+        assert!(xml.ends_with(r#"<reg name="mxcsr" bitsize="32" type="i386_mxcsr"/></feature></target>"#));
+    }
+
+    #[test]
+    fn target_xml_escapes_names() {
+        let regs = DynamicRegisters::new(Some("a<b".into()), "f&g", vec![RegDesc::new("r\"0", 32, "uint32")]);
+        let xml = target_xml::<Dynamic<LittleEndian>>(&regs).unwrap();
+        assert!(xml.contains(concat!(
+            r#"<architecture>a&lt;b</architecture><feature name="f&amp;g">"#,
+            r#"<reg name="r&quot;0" bitsize="32" type="uint32"/></feature>"#,
+        )));
+    }
+
+    #[test]
+    fn target_xml_requires_complete_descriptions() {
+        // doesn't implement `describe`
+        struct Opaque(u32);
+
+        impl Register for Opaque {
+            fn encode<C: Comm, B: ByteOrder>(&self, comm: &mut C) -> Result<(), C::Error> {
+                self.0.encode::<C, B>(comm)
+            }
+
+            fn decode<R: Read, B: ByteOrder>(reader: &mut R) -> Result<Self, io::Error> {
+                Ok(Opaque(u32::decode::<R, B>(reader)?))
+            }
+        }
+
+        struct OpaqueTarget;
+
+        impl TargetDesc for OpaqueTarget {
+            type Registers = Opaque;
+            type Endianness = LittleEndian;
+        }
+
+        assert!(target_xml::<OpaqueTarget>(&Opaque(0)).is_none());
+    }
+}
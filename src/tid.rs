@@ -0,0 +1,54 @@
+use Comm;
+
+use std::fmt;
+
+/// Identifies a thread of a process (inferior) on the target.
+///
+/// Both IDs must be non-zero. Targets that don't distinguish processes or
+/// threads can use `Tid::default()`, which is thread 1 of process 1. Multiple
+/// processes can be used to model, for example, several independent cores of
+/// an emulated system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Tid {
+    /// The process ID.
+    pub pid: u32,
+    /// The thread ID within the process.
+    pub tid: u32,
+}
+
+impl Tid {
+    /// Creates a thread identifier.
+    pub fn new(pid: u32, tid: u32) -> Self {
+        assert!(pid != 0 && tid != 0, "process and thread IDs must not be 0");
+        Tid { pid, tid }
+    }
+
+    /// Writes the thread ID in the format expected by the debugger.
+    ///
+    /// The process ID is only included if the multiprocess extension is in
+    /// use.
+    pub(crate) fn write<C: Comm>(&self, comm: &mut C, multiprocess: bool) -> Result<(), C::Error> {
+        comm.write_all(self.to_protocol(multiprocess).as_bytes())
+    }
+
+    /// Formats the thread ID the way [`write`](#method.write) sends it.
+    pub(crate) fn to_protocol(self, multiprocess: bool) -> String {
+        if multiprocess {
+            format!("p{:x}.{:x}", self.pid, self.tid)
+        } else {
+            format!("{:x}", self.tid)
+        }
+    }
+}
+
+impl Default for Tid {
+    fn default() -> Self {
+        Tid { pid: 1, tid: 1 }
+    }
+}
+
+impl fmt::Display for Tid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "p{}.{}", self.pid, self.tid)
+    }
+}
@@ -0,0 +1,351 @@
+//! Tracepoints and the trace buffer.
+//!
+//! While a trace run is active, the stub installs a breakpoint at every
+//! enabled tracepoint. When the target hits one, the registers and the memory
+//! described by the tracepoint's actions are collected into a trace frame and
+//! the target is resumed without notifying GDB. Afterwards, GDB selects frames
+//! with `QTFrame` and reads their registers and memory as if the target was
+//! stopped there.
+//!
+//! The registers are collected at every hit, regardless of the tracepoint's
+//! actions. `while-stepping` actions aren't supported.
+
+use StubCalls;
+use agent::{Agent, AgentError};
+use targets::raw_registers;
+
+/// Size of the trace buffer in bytes.
+pub(crate) const TRACE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Data collected when a tracepoint is hit, in addition to the registers.
+#[derive(Debug)]
+pub(crate) enum TraceAction {
+    /// Collect the registers.
+    Registers,
+    /// Collect `len` bytes of memory at `offset` from the value of register
+    /// `basereg`, or at the absolute address `offset`.
+    Memory { basereg: Option<u16>, offset: u64, len: u64 },
+    /// Run an agent expression, which collects data with `trace`
+    /// instructions.
+    Expr(Vec<u8>),
+}
+
+/// A tracepoint downloaded by GDB.
+pub(crate) struct Tracepoint {
+    pub number: u32,
+    pub addr: u64,
+    pub enabled: bool,
+    /// Number of hits after which the trace run stops, or 0 for no limit.
+    pub pass_count: u64,
+    /// Agent expression that has to be true for the tracepoint to collect.
+    pub condition: Option<Vec<u8>>,
+    pub actions: Vec<TraceAction>,
+    /// Number of frames collected in the current trace run.
+    pub hits: u64,
+}
+
+/// A trace state variable, which agent expressions can read and modify.
+pub(crate) struct TraceVariable {
+    pub number: u32,
+    /// The value at the start of a trace run.
+    pub initial: i64,
+    pub value: i64,
+}
+
+/// Data collected at a tracepoint hit.
+struct TraceFrame {
+    tracepoint: u32,
+    pc: u64,
+    /// The raw register contents, in the format they are sent to GDB in.
+    regs: Vec<u8>,
+    memory: Vec<(u64, Vec<u8>)>,
+    variables: Vec<(u32, i64)>,
+}
+
+impl TraceFrame {
+    /// Returns the number of trace buffer bytes used by the frame.
+    fn size(&self) -> usize {
+        self.regs.len()
+            + self.memory.iter().map(|(_, bytes)| bytes.len()).sum::<usize>()
+            + self.variables.len() * 8
+    }
+}
+
+/// Why the last trace run stopped.
+enum TraceStop {
+    NotRun,
+    /// GDB sent `QTStop`.
+    User,
+    BufferFull,
+    /// The tracepoint with the given number reached its pass count.
+    PassCount(u32),
+    /// Evaluating an agent expression of a tracepoint failed.
+    Error { tracepoint: u32, message: String },
+}
+
+/// Selects a trace frame, see `QTFrame`.
+#[derive(Debug)]
+pub(crate) enum FrameSelector {
+    /// Stop looking at trace frames.
+    None,
+    Number(usize),
+    /// The next frame whose PC is the address.
+    Pc(u64),
+    /// The next frame collected by the tracepoint with the given number.
+    Tracepoint(u32),
+    /// The next frame whose PC is in the inclusive range.
+    Range { start: u64, end: u64 },
+    /// The next frame whose PC is outside of the inclusive range.
+    Outside { start: u64, end: u64 },
+}
+
+/// The tracepoints and the collected trace frames.
+pub(crate) struct Trace {
+    pub tracepoints: Vec<Tracepoint>,
+    pub variables: Vec<TraceVariable>,
+    /// Memory regions that can be read from the target while looking at a
+    /// trace frame, as `(start, end)` pairs.
+    pub readonly: Vec<(u64, u64)>,
+    frames: Vec<TraceFrame>,
+    /// Bytes used by `frames`.
+    used: usize,
+    running: bool,
+    stop: TraceStop,
+    /// The frame selected by GDB, whose data is shown instead of the target's.
+    selected: Option<usize>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Trace {
+            tracepoints: Vec::new(),
+            variables: Vec::new(),
+            readonly: Vec::new(),
+            frames: Vec::new(),
+            used: 0,
+            running: false,
+            stop: TraceStop::NotRun,
+            selected: None,
+        }
+    }
+
+    /// Returns whether a trace run is active.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns the addresses of the enabled tracepoints.
+    pub fn addresses(&self) -> Vec<u64> {
+        let mut addrs: Vec<_> = self.tracepoints.iter()
+            .filter(|tp| tp.enabled)
+            .map(|tp| tp.addr)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Deletes all tracepoints, trace state variables and trace frames.
+    pub fn clear(&mut self) {
+        *self = Trace::new();
+    }
+
+    /// Starts a trace run, discarding the frames of the previous one.
+    pub fn start(&mut self) {
+        self.frames.clear();
+        self.used = 0;
+        self.selected = None;
+        for tp in &mut self.tracepoints {
+            tp.hits = 0;
+        }
+        for var in &mut self.variables {
+            var.value = var.initial;
+        }
+        self.running = true;
+    }
+
+    /// Stops the trace run on GDB's request.
+    pub fn stop(&mut self) {
+        self.finish(TraceStop::User);
+    }
+
+    fn finish(&mut self, reason: TraceStop) {
+        if self.running {
+            self.running = false;
+            self.stop = reason;
+        }
+    }
+
+    /// Collects trace frames for the tracepoints at `pc`.
+    ///
+    /// Returns whether there is an enabled tracepoint at `pc`.
+    pub fn hit<T: StubCalls>(&mut self, target: &mut T, pc: u64) -> bool {
+        let mut found = false;
+        for i in 0..self.tracepoints.len() {
+            if !self.running {
+                break;
+            }
+            let (number, addr, enabled) = {
+                let tp = &self.tracepoints[i];
+                (tp.number, tp.addr, tp.enabled)
+            };
+            if addr != pc || !enabled {
+                continue;
+            }
+
+            found = true;
+            match self.collect(target, i, pc) {
+                Ok(Some(frame)) => {
+                    if self.used + frame.size() > TRACE_BUFFER_SIZE {
+                        self.finish(TraceStop::BufferFull);
+                        break;
+                    }
+                    self.used += frame.size();
+                    self.frames.push(frame);
+
+                    let tp = &mut self.tracepoints[i];
+                    tp.hits += 1;
+                    if tp.pass_count != 0 && tp.hits >= tp.pass_count {
+                        self.finish(TraceStop::PassCount(number));
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("failed to collect tracepoint {} at {:#x}: {}", number, pc, e);
+                    self.finish(TraceStop::Error { tracepoint: number, message: e.to_string() });
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Runs the actions of tracepoint `i`, unless its condition is false.
+    fn collect<T: StubCalls>(&mut self, target: &mut T, i: usize, pc: u64) -> Result<Option<TraceFrame>, AgentError> {
+        let tp = &self.tracepoints[i];
+        let (memory, variables) = {
+            let mut agent = Agent::new(&mut *target).with_variables(&mut self.variables);
+            if let Some(ref condition) = tp.condition {
+                if agent.eval(condition)? == 0 {
+                    return Ok(None);
+                }
+            }
+
+            for action in &tp.actions {
+                match *action {
+                    // the registers are always collected
+                    TraceAction::Registers => {}
+                    TraceAction::Memory { basereg, offset, len } => {
+                        let base = match basereg {
+                            Some(reg) => agent.register(reg)?,
+                            None => 0,
+                        };
+                        agent.collect(base.wrapping_add(offset), len)?;
+                    }
+                    TraceAction::Expr(ref code) => {
+                        agent.eval(code)?;
+                    }
+                }
+            }
+
+            (agent.take_memory(), agent.take_variables())
+        };
+
+        Ok(Some(TraceFrame {
+            tracepoint: tp.number,
+            pc,
+            regs: raw_registers::<T::Target>(&target.read_registers()),
+            memory,
+            variables,
+        }))
+    }
+
+    /// Selects a trace frame, returning its number and the number of the
+    /// tracepoint that collected it.
+    ///
+    /// Searches start after the currently selected frame. If no frame
+    /// matches, no frame is selected.
+    pub fn select(&mut self, selector: &FrameSelector) -> Option<(usize, u32)> {
+        let next = self.selected.map_or(0, |frame| frame + 1);
+        let found = match *selector {
+            FrameSelector::None => None,
+            FrameSelector::Number(n) => if n < self.frames.len() { Some(n) } else { None },
+            FrameSelector::Pc(addr) => self.find(next, |frame| frame.pc == addr),
+            FrameSelector::Tracepoint(tp) => self.find(next, |frame| frame.tracepoint == tp),
+            FrameSelector::Range { start, end } => self.find(next, |frame| frame.pc >= start && frame.pc <= end),
+            FrameSelector::Outside { start, end } => self.find(next, |frame| frame.pc < start || frame.pc > end),
+        };
+
+        self.selected = found;
+        found.map(|n| (n, self.frames[n].tracepoint))
+    }
+
+    fn find<F: Fn(&TraceFrame) -> bool>(&self, start: usize, f: F) -> Option<usize> {
+        self.frames.iter().enumerate().skip(start).find(|&(_, frame)| f(frame)).map(|(n, _)| n)
+    }
+
+    /// Returns the registers of the selected frame, if any.
+    pub fn frame_registers(&self) -> Option<&[u8]> {
+        self.selected.map(|n| &*self.frames[n].regs)
+    }
+
+    /// Returns whether a frame is selected.
+    pub fn frame_selected(&self) -> bool {
+        self.selected.is_some()
+    }
+
+    /// Reads a byte of memory while a frame is selected.
+    ///
+    /// Memory that wasn't collected can only be read if it is in a read-only
+    /// region.
+    pub fn read_mem<T: StubCalls>(&self, target: &mut T, addr: u64) -> Result<u8, ()> {
+        let frame = &self.frames[self.selected.ok_or(())?];
+        let collected = frame.memory.iter().rev().find_map(|&(start, ref bytes)| {
+            addr.checked_sub(start).and_then(|offset| bytes.get(offset as usize)).cloned()
+        });
+        match collected {
+            Some(byte) => Ok(byte),
+            None if self.readonly.iter().any(|&(start, end)| addr >= start && addr < end) => target.read_mem(addr),
+            None => Err(()),
+        }
+    }
+
+    /// Returns the value of trace state variable `number`, as collected in
+    /// the selected frame or as currently known.
+    pub fn variable_value(&self, number: u32) -> Option<i64> {
+        match self.selected {
+            Some(n) => self.frames[n].variables.iter().rev()
+                .find(|&&(var, _)| var == number)
+                .map(|&(_, value)| value),
+            None => self.variables.iter().find(|var| var.number == number).map(|var| var.value),
+        }
+    }
+
+    /// Returns the reply to `qTStatus`.
+    pub fn status(&self) -> String {
+        let mut status = if self.running {
+            String::from("T1")
+        } else {
+            let reason = match self.stop {
+                TraceStop::NotRun => String::from("tnotrun:0"),
+                TraceStop::User => String::from("tstop::0"),
+                TraceStop::BufferFull => String::from("tfull:0"),
+                TraceStop::PassCount(tp) => format!("tpasscount:{:x}", tp),
+                TraceStop::Error { tracepoint, ref message } => {
+                    let message: String = message.bytes().map(|b| format!("{:02x}", b)).collect();
+                    format!("terror:{}:{:x}", message, tracepoint)
+                }
+            };
+            format!("T0;{}", reason)
+        };
+
+        status.push_str(&format!(
+            ";tframes:{:x};tcreated:{:x};tfree:{:x};tsize:{:x};circular:0;disconn:0",
+            self.frames.len(),
+            self.frames.len(),
+            TRACE_BUFFER_SIZE - self.used,
+            TRACE_BUFFER_SIZE,
+        ));
+        status
+    }
+}
@@ -1,3 +1,8 @@
+use StubCalls;
+use targets::TargetDesc;
+
+use byteorder::ByteOrder;
+use std::fmt;
 use std::str::{self, Utf8Error};
 use std::num::ParseIntError;
 
@@ -8,11 +13,69 @@ pub fn hex_decode_in_place(bytes: &mut [u8]) -> Result<&[u8], HexDecodeError> {
     Ok(&bytes[..bytes.len()/2])
 }
 
+/// Reads an unsigned integer of `size` bytes (at most 8) from target memory,
+/// using the target's byte order.
+pub fn read_uint<T: StubCalls>(target: &mut T, addr: u64, size: usize) -> Result<u64, ()> {
+    let mut buf = [0; 8];
+    for (i, byte) in buf[..size].iter_mut().enumerate() {
+        *byte = target.read_mem(addr.checked_add(i as u64).ok_or(())?)?;
+    }
+    Ok(<<T::Target as TargetDesc>::Endianness as ByteOrder>::read_uint(&buf, size))
+}
+
+/// Escapes special characters for use in XML text or attributes.
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Updates the CRC-32 `crc` with `byte`, the way GDB computes it for `qCRC`.
+///
+/// This is the non-reflected CRC with polynomial `0x04c11db7`, processing the
+/// most significant bit first. GDB starts with `0xffffffff` and doesn't invert
+/// the result.
+pub fn crc32_update(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ (u32::from(byte) << 24);
+    for _ in 0..8 {
+        crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04c1_1db7 } else { crc << 1 };
+    }
+    crc
+}
+
+/// Decodes binary data sent by the debugger in place.
+///
+/// Bytes with a special meaning in the protocol are escaped by prefixing them
+/// with `}` and XORing them with `0x20`. Returns the decoded part of `bytes`.
+pub fn unescape_in_place(bytes: &mut [u8]) -> &[u8] {
+    let mut read = 0;
+    let mut written = 0;
+    while read < bytes.len() {
+        if bytes[read] == b'}' && read + 1 < bytes.len() {
+            bytes[written] = bytes[read + 1] ^ 0x20;
+            read += 2;
+        } else {
+            bytes[written] = bytes[read];
+            read += 1;
+        }
+        written += 1;
+    }
+    &bytes[..written]
+}
+
+#[derive(Debug)]
 pub enum HexDecodeError {
     Utf8Error(Utf8Error),
     ParseIntError(ParseIntError),
 }
 
+impl fmt::Display for HexDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HexDecodeError::Utf8Error(e) => write!(f, "invalid hex string: {}", e),
+            HexDecodeError::ParseIntError(e) => write!(f, "invalid hex digits: {}", e),
+        }
+    }
+}
+
 impl From<Utf8Error> for HexDecodeError {
     fn from(e: Utf8Error) -> Self {
         HexDecodeError::Utf8Error(e)
@@ -24,3 +87,20 @@ impl From<ParseIntError> for HexDecodeError {
         HexDecodeError::ParseIntError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape() {
+        let mut buf = *b"a}]}\x03}";
+        assert_eq!(unescape_in_place(&mut buf), b"a}#}");
+    }
+
+    #[test]
+    fn crc32() {
+        // the check value of CRC-32/MPEG-2
+        assert_eq!(b"123456789".iter().fold(0xffff_ffff, |crc, &b| crc32_update(crc, b)), 0x0376_e6e7);
+    }
+}